@@ -0,0 +1,170 @@
+/*
+    Copyright (C) 2020 Arkadiusz Guzinski <kermit@ag.de1.cc>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::thread;
+use serde::{Serialize, Deserialize};
+use crate::memdump::MemDump;
+
+/// a set of addresses inside ranges of memory, as bitmap with one bit per address. Used for the
+/// candidates of memset-diff and memsearch, which start as all bytes of several megabytes.
+#[derive(Serialize, Deserialize)]
+pub struct AddressSet {
+    /// (first address, last address + 1, one bit per address, lowest bit first)
+    ranges: Vec<(u32, u32, Vec<u64>)>,
+}
+
+impl AddressSet {
+    /// set of all addresses in ranges (first address, last address + 1)
+    pub fn full(ranges: &[(u32, u32)]) -> AddressSet {
+        AddressSet {
+            ranges: ranges.iter().map(|(from, to)| {
+                let len = (to - from) as usize;
+                let mut bits = vec![u64::MAX; len.div_ceil(64)];
+                if let Some(last) = bits.last_mut().filter(|_| !len.is_multiple_of(64)) {
+                    *last = (1 << (len % 64)) - 1;
+                }
+                (*from, *to, bits)
+            }).collect()
+        }
+    }
+
+    /// number of addresses in the set
+    pub fn len(&self) -> usize {
+        self.ranges.iter().map(|(_, _, bits)| bits.iter().map(|w| w.count_ones() as usize).sum::<usize>()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.iter().all(|(_, _, bits)| bits.iter().all(|w| *w == 0))
+    }
+
+    /// addresses in the set, ascending
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.ranges.iter().flat_map(|(from, _, bits)| bits.iter().enumerate().flat_map(move |(i, word)| {
+            let word = *word;
+            (0..64u32).filter(move |bit| word >> bit & 1 != 0).map(move |bit| from + 64 * i as u32 + bit)
+        }))
+    }
+
+    /// keep only the addresses for which keep returns true. It is called in ascending order.
+    pub fn retain(&mut self, mut keep: impl FnMut(u32) -> bool) {
+        for (from, _, bits) in self.ranges.iter_mut() {
+            for (i, word) in bits.iter_mut().enumerate() {
+                let mut rest = *word;
+                while rest != 0 {
+                    let bit = rest.trailing_zeros();
+                    if !keep(*from + 64 * i as u32 + bit) {
+                        *word &= !(1 << bit);
+                    }
+                    rest &= rest - 1;
+                }
+            }
+        }
+    }
+
+    /// keep only the addresses whose bytes in a and b are equal (equal = true) or differ. Both dumps
+    /// must contain all ranges of the set. The ranges are split up and compared in parallel.
+    pub fn retain_compared(&mut self, a: &MemDump, b: &MemDump, equal: bool) {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        self.retain_compared_in(a, b, equal, threads);
+    }
+
+    /// retain_compared, with each range split up for the given number of threads
+    fn retain_compared_in(&mut self, a: &MemDump, b: &MemDump, equal: bool, threads: usize) {
+        for (from, to, bits) in self.ranges.iter_mut() {
+            let len = (*to - *from) as usize;
+            let left = a.bytes_at(*from, len).expect("range not inside first dump");
+            let right = b.bytes_at(*from, len).expect("range not inside second dump");
+            let words_per_thread = bits.len().div_ceil(threads).max(1);
+            thread::scope(|scope| {
+                for (t, chunk) in bits.chunks_mut(words_per_thread).enumerate() {
+                    scope.spawn(move || {
+                        for (w, word) in chunk.iter_mut().enumerate() {
+                            if *word == 0 {
+                                continue;
+                            }
+                            let start = 64 * (t * words_per_thread + w);
+                            let end = (start + 64).min(len);
+                            let same = left[start..end].iter().zip(&right[start..end]).enumerate()
+                                .fold(0u64, |mask, (bit, (l, r))| if l == r { mask | 1 << bit } else { mask });
+                            *word &= if equal { same } else { !same };
+                        }
+                    });
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ranges whose lengths are no multiples of 64, the first one split up into many words
+    const RANGES: [(u32, u32); 2] = [(0x1000, 0x1000 + 64 * 1000 + 37), (0x40001, 0x40006)];
+
+    /// two dumps of RANGES, differing at the given addresses
+    fn dumps(differing: &[u32]) -> (MemDump, MemDump) {
+        let (mut a, mut b) = (MemDump::new(), MemDump::new());
+        for (from, to) in RANGES {
+            let data: Vec<u8> = (from..to).map(|address| (address % 251) as u8).collect();
+            let mut changed = data.clone();
+            for address in differing.iter().filter(|address| (from..to).contains(address)) {
+                changed[(address - from) as usize] ^= 0xFF;
+            }
+            a.add_part(from, data);
+            b.add_part(from, changed);
+        }
+        (a, b)
+    }
+
+    #[test]
+    fn full() {
+        let set = AddressSet::full(&RANGES);
+        assert_eq!(set.len(), 64 * 1000 + 37 + 5);
+        assert!(!set.is_empty());
+        let addresses: Vec<u32> = set.iter().collect();
+        assert_eq!(addresses.len(), set.len());
+        assert_eq!(addresses.first(), Some(&0x1000));
+        assert_eq!(addresses.last(), Some(&0x40005));
+        assert!(addresses.windows(2).all(|w| w[0] < w[1]));
+        assert!(!addresses.contains(&(0x1000 + 64 * 1000 + 37)));
+        assert!(AddressSet::full(&[]).is_empty());
+    }
+
+    #[test]
+    fn retain_compared() {
+        // first and last address, word boundaries and the short range
+        let differing = [0x1000, 0x103F, 0x1040, 0x1000 + 64 * 500 + 1, 0x1000 + 64 * 1000 + 36, 0x40003];
+        let (a, b) = dumps(&differing);
+        for threads in [1, 3, 8] {
+            let mut changed = AddressSet::full(&RANGES);
+            changed.retain_compared_in(&a, &b, false, threads);
+            assert_eq!(changed.iter().collect::<Vec<u32>>(), differing);
+
+            let mut unchanged = AddressSet::full(&RANGES);
+            unchanged.retain_compared_in(&a, &b, true, threads);
+            assert_eq!(unchanged.len(), 64 * 1000 + 37 + 5 - differing.len());
+            assert!(unchanged.iter().all(|address| !differing.contains(&address)));
+
+            // removed addresses stay removed
+            unchanged.retain_compared_in(&a, &b, false, threads);
+            assert!(unchanged.is_empty());
+        }
+        let mut changed = AddressSet::full(&RANGES);
+        changed.retain_compared(&a, &b, false);
+        assert_eq!(changed.len(), differing.len());
+    }
+}
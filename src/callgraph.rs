@@ -0,0 +1,153 @@
+/*
+    Copyright (C) 2020 Arkadiusz Guzinski <kermit@ag.de1.cc>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use crate::utils::FormatHelper;
+
+/// a function in the call graph, identified by its entry pc
+#[derive(Default)]
+pub struct Node {
+    /// instructions executed in this function itself
+    pub exclusive: usize,
+    /// number of times this function was entered
+    pub calls: usize,
+}
+
+/// aggregated calls from one function to another
+#[derive(Default)]
+pub struct Edge {
+    /// number of calls, 0 if the callee was called before the dump started
+    pub calls: usize,
+    /// instructions executed inside the callee (and everything it called) for these calls
+    pub instructions: usize,
+}
+
+/// caller -> callee graph of a dump
+pub struct CallGraph {
+    pub nodes: BTreeMap<u32, Node>,
+    pub edges: BTreeMap<(u32, u32), Edge>,
+}
+
+impl CallGraph {
+    pub fn new() -> CallGraph {
+        CallGraph { nodes: BTreeMap::new(), edges: BTreeMap::new() }
+    }
+
+    /// add instructions executed inside function entry
+    pub fn add_instructions(&mut self, entry: u32, count: usize) {
+        self.nodes.entry(entry).or_default().exclusive += count;
+    }
+
+    /// record a call from caller to callee
+    pub fn add_call(&mut self, caller: u32, callee: u32) {
+        self.nodes.entry(caller).or_default();
+        self.nodes.entry(callee).or_default().calls += 1;
+        self.edges.entry((caller, callee)).or_default().calls += 1;
+    }
+
+    /// add instructions executed by callee on behalf of caller (when callee returns)
+    pub fn add_inclusive(&mut self, caller: u32, callee: u32, count: usize) {
+        self.nodes.entry(caller).or_default();
+        self.nodes.entry(callee).or_default();
+        self.edges.entry((caller, callee)).or_default().instructions += count;
+    }
+
+    /// reduce graph to the nodes reachable from root
+    pub fn reachable_from(self, root: u32) -> CallGraph {
+        let mut visited: BTreeSet<u32> = BTreeSet::new();
+        let mut queue: VecDeque<u32> = VecDeque::new();
+        queue.push_back(root);
+        while let Some(n) = queue.pop_front() {
+            if !visited.insert(n) {
+                continue;
+            }
+            for (caller, callee) in self.edges.keys() {
+                if *caller == n && !visited.contains(callee) {
+                    queue.push_back(*callee);
+                }
+            }
+        }
+        let CallGraph { nodes, edges } = self;
+        CallGraph {
+            nodes: nodes.into_iter().filter(|(k, _)| visited.contains(k)).collect(),
+            edges: edges.into_iter().filter(|((from, _), _)| visited.contains(from)).collect(),
+        }
+    }
+
+    /// find entry pc of a node by function name or pc (as printed, dump or translated address)
+    pub fn find_node(&self, name_or_pc: &str, fmt: &FormatHelper) -> Option<u32> {
+        if let Ok(pc) = u32::from_str_radix(name_or_pc, 16) {
+            if self.nodes.contains_key(&pc) {
+                return Some(pc);
+            }
+            if let Some(n) = self.nodes.keys().find(|n| fmt.with_offset(**n) == pc) {
+                return Some(*n);
+            }
+        }
+        self.nodes.keys().find(|n| fmt.name_for(**n).as_deref() == Some(name_or_pc)).copied()
+    }
+
+    /// write graph in Graphviz' DOT format
+    pub fn to_dot(&self, fmt: &FormatHelper) -> String {
+        let mut s = String::from("digraph calls {\n    node [shape=box];\n");
+        for (entry, node) in &self.nodes {
+            s += format!("    \"{:08X}\" [label=\"{}\\n{} calls, {} instr\"];\n",
+                         entry, escape(&fmt.pc(*entry)), node.calls, node.exclusive).as_str();
+        }
+        for ((from, to), edge) in &self.edges {
+            // calls from before the dump are dashed
+            let style = if edge.calls == 0 { ", style=dashed" } else { "" };
+            s += format!("    \"{:08X}\" -> \"{:08X}\" [label=\"{}x / {}\"{}];\n",
+                         from, to, edge.calls, edge.instructions, style).as_str();
+        }
+        s += "}";
+        s
+    }
+
+    /// write graph as GraphML (e.g. for yEd)
+    pub fn to_graphml(&self, fmt: &FormatHelper) -> String {
+        let mut s = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n\
+            \x20 <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n\
+            \x20 <key id=\"ncalls\" for=\"node\" attr.name=\"calls\" attr.type=\"int\"/>\n\
+            \x20 <key id=\"exclusive\" for=\"node\" attr.name=\"instructions\" attr.type=\"int\"/>\n\
+            \x20 <key id=\"ecalls\" for=\"edge\" attr.name=\"calls\" attr.type=\"int\"/>\n\
+            \x20 <key id=\"inclusive\" for=\"edge\" attr.name=\"instructions\" attr.type=\"int\"/>\n\
+            \x20 <graph id=\"calls\" edgedefault=\"directed\">\n");
+        for (entry, node) in &self.nodes {
+            s += format!("    <node id=\"n{:08X}\">\n      <data key=\"label\">{}</data>\n      \
+                <data key=\"ncalls\">{}</data>\n      <data key=\"exclusive\">{}</data>\n    </node>\n",
+                         entry, escape_xml(&fmt.pc(*entry)), node.calls, node.exclusive).as_str();
+        }
+        for ((from, to), edge) in &self.edges {
+            s += format!("    <edge source=\"n{:08X}\" target=\"n{:08X}\">\n      \
+                <data key=\"ecalls\">{}</data>\n      <data key=\"inclusive\">{}</data>\n    </edge>\n",
+                         from, to, edge.calls, edge.instructions).as_str();
+        }
+        s += "  </graph>\n</graphml>";
+        s
+    }
+}
+
+/// escape string for use in DOT labels
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// escape string for use in XML
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
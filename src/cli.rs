@@ -34,6 +34,19 @@ pub fn args() -> clap::App<'static> {
         .arg(Arg::new("traps").short('t').global(true)
             .about("show interrupts (traps)")
         )
+        .arg(Arg::new("interrupt-mode").short('x').long("interrupts").global(true)
+            .about("analyse steps in interrupt handlers: include (default), exclude, only or fold them")
+            .possible_values(&["include", "exclude", "only", "fold"])
+        )
+        .arg(Arg::new("rom-mode").short('k').long("rom").global(true)
+            .about("analyse steps in ROM and OS code (see ranges in help-fs): include (default), collapse or exclude them")
+            .possible_values(&["include", "collapse", "exclude"])
+        )
+        .arg(Arg::new("kickstart").short('K').long("kickstart").global(true)
+            .about("Kickstart ROM image used for ROM memory and names (default: kickstart.rom, see help-fs)")
+            .takes_value(true)
+            .value_hint(ValueHint::FilePath)
+        )
 
         .subcommand(App::new("search-value").visible_aliases(&["d", "D"])
             .setting(AppSettings::ArgRequiredElseHelp)
@@ -83,6 +96,9 @@ pub fn args() -> clap::App<'static> {
                 .about("values to highlight; format is as printed (hex)")
                 .value_hint(ValueHint::Other)
             )
+            .arg(Arg::new("raw").short('r').long("raw")
+                .about("do not collapse loops")
+            )
         )
 
         .subcommand(App::new("summary").visible_aliases(&["s", "S"])
@@ -104,6 +120,9 @@ pub fn args() -> clap::App<'static> {
                 .about("values to highlight; format is as printed (hex)")
                 .value_hint(ValueHint::Other)
             )
+            .arg(Arg::new("raw").short('r').long("raw")
+                .about("do not collapse loops")
+            )
         )
 
         .subcommand(App::new("print-ghidra-search-pattern").visible_alias("g")
@@ -118,7 +137,17 @@ pub fn args() -> clap::App<'static> {
                 .value_hint(ValueHint::Other)
             )
             .arg(Arg::new("count").index(3)
-                .about("number of instructions to print after pc (default: 30)")
+                .about("number of distinct instructions to include after pc (default: 30)")
+                .value_hint(ValueHint::Other)
+            )
+            .arg(Arg::new("exe").short('e').long("exe")
+                .about("AmigaOS executable (hunk format), to mask relocated addresses")
+                .takes_value(true)
+                .value_hint(ValueHint::FilePath)
+            )
+            .arg(Arg::new("base").short('B').long("base")
+                .about("address Ghidra loaded the first hunk to (hex, default: 21F000)")
+                .takes_value(true)
                 .value_hint(ValueHint::Other)
             )
         )
@@ -181,6 +210,28 @@ pub fn args() -> clap::App<'static> {
                 .about("dir containing directories named set_id (see help-fs)")
                 .value_hint(ValueHint::DirPath)
             )
+            .arg(Arg::new("value").short('v').long("value")
+                .about("known value (dec) per set, as set=value: print addresses holding it in all dumps of the set")
+                .takes_value(true).multiple(true)
+                .value_hint(ValueHint::Other)
+            )
+            .arg(Arg::new("format").short('f').long("format")
+                .about("consecutive bytes grouped with the values of each set as table (default), CSV, JSON, or one address per line")
+                .possible_values(&["table", "csv", "json", "list"])
+            )
+            .arg(Arg::new("out").short('w').long("out")
+                .about("write to file instead of stdout")
+                .takes_value(true)
+                .value_hint(ValueHint::FilePath)
+            )
+            .arg(Arg::new("encoding").short('e').long("encoding")
+                .about("encodings of known values: big endian 8, 16, 32 bit or BCD (default: all)")
+                .takes_value(true).multiple(true)
+                .possible_values(&["8", "16", "32", "bcd"])
+            )
+            .arg(Arg::new("unaligned").short('u').long("unaligned")
+                .about("also find known values of more than one byte at odd addresses (68020 and later)")
+            )
         )
 
         .subcommand(App::new("calls").visible_aliases(&["c", "C"])
@@ -192,5 +243,259 @@ pub fn args() -> clap::App<'static> {
             )
         )
 
+        .subcommand(App::new("callgraph").visible_alias("cg")
+            .setting(AppSettings::ArgRequiredElseHelp)
+            .about("export call graph with call and instruction counts as DOT or GraphML")
+            .arg(Arg::new("dir").required(true).index(1)
+                .about("directory containing the dump")
+                .value_hint(ValueHint::DirPath)
+            )
+            .arg(Arg::new("format").short('f').long("format")
+                .about("output format (default: dot)")
+                .possible_values(&["dot", "graphml"])
+            )
+            .arg(Arg::new("from").short('r').long("from")
+                .about("only export functions reachable from this function (name or pc in hex)")
+                .takes_value(true)
+                .value_hint(ValueHint::Other)
+            )
+        )
+
+        .subcommand(App::new("profile").visible_alias("prof")
+            .setting(AppSettings::ArgRequiredElseHelp)
+            .about("print instructions executed per call stack (folded, for flame graphs) or per function")
+            .arg(Arg::new("dir").required(true).index(1)
+                .about("directory containing the dump")
+                .value_hint(ValueHint::DirPath)
+            )
+            .arg(Arg::new("format").short('f').long("format")
+                .about("folded stacks (default) or table of inclusive/exclusive counts per function")
+                .possible_values(&["folded", "table"])
+            )
+        )
+
+        .subcommand(App::new("comparisons").visible_alias("cmp")
+            .setting(AppSettings::ArgRequiredElseHelp)
+            .about("print comparisons (CMP/TST/BTST) involving a value and the following branch")
+            .arg(Arg::new("dir").required(true).index(1)
+                .about("directory containing the dump")
+                .value_hint(ValueHint::DirPath)
+            )
+            .arg(Arg::new("val").required(true).index(2)
+                .about("value (dec) to look for in registers and immediate operands")
+                .value_hint(ValueHint::Other)
+            )
+        )
+
+        .subcommand(App::new("exceptions").visible_alias("ex")
+            .setting(AppSettings::ArgRequiredElseHelp)
+            .about("list exceptions (traps, interrupts, ...) with vector, handler and return point")
+            .arg(Arg::new("dir").required(true).index(1)
+                .about("directory containing the dump and optionally memory with the vector table")
+                .value_hint(ValueHint::DirPath)
+            )
+        )
+
+        .subcommand(App::new("interrupts").visible_alias("irq")
+            .setting(AppSettings::ArgRequiredElseHelp)
+            .about("list interrupt handler invocations with duration in steps and totals per level")
+            .arg(Arg::new("dir").required(true).index(1)
+                .about("directory containing the dump")
+                .value_hint(ValueHint::DirPath)
+            )
+        )
+
+        .subcommand(App::new("hw-log").visible_alias("hw")
+            .setting(AppSettings::ArgRequiredElseHelp)
+            .about("print all writes to custom chip and CIA registers with step index, pc and value")
+            .arg(Arg::new("dir").required(true).index(1)
+                .about("directory containing the dump")
+                .value_hint(ValueHint::DirPath)
+            )
+        )
+
+        .subcommand(App::new("coverage").visible_alias("cov")
+            .setting(AppSettings::ArgRequiredElseHelp)
+            .about("write addresses of executed instructions (translated by offset) as list or drcov")
+            .arg(Arg::new("dir").required(true).index(1)
+                .about("directory containing the dump")
+                .value_hint(ValueHint::DirPath)
+            )
+            .arg(Arg::new("without").multiple(true).index(2)
+                .about("directories with dumps whose executed instructions are excluded")
+                .value_hint(ValueHint::DirPath)
+            )
+            .arg(Arg::new("format").short('f').long("format")
+                .about("plain address list (default) or drcov (e.g. for Ghidra's Dragon Dance / Lighthouse)")
+                .possible_values(&["list", "drcov"])
+            )
+            .arg(Arg::new("out").short('w').long("out")
+                .about("write to file instead of stdout")
+                .takes_value(true)
+                .value_hint(ValueHint::FilePath)
+            )
+            .arg(Arg::new("module").short('m').long("module")
+                .about("module name for drcov (default: program)")
+                .takes_value(true)
+                .value_hint(ValueHint::Other)
+            )
+        )
+
+        .subcommand(App::new("find-offset").visible_alias("fo")
+            .setting(AppSettings::ArgRequiredElseHelp)
+            .about("find the hunks of an executable in the dump and write the offset file")
+            .arg(Arg::new("dir").required(true).index(1)
+                .about("directory containing the dump and optionally memory")
+                .value_hint(ValueHint::DirPath)
+            )
+            .arg(Arg::new("exe").required(true).index(2)
+                .about("AmigaOS executable (hunk format), as loaded in Ghidra")
+                .value_hint(ValueHint::FilePath)
+            )
+            .arg(Arg::new("base").short('B').long("base")
+                .about("address Ghidra loaded the first hunk to (hex, default: 21F000)")
+                .takes_value(true)
+                .value_hint(ValueHint::Other)
+            )
+            .arg(Arg::new("dry-run").short('p').long("dry-run")
+                .about("only print the report, don't write the offset file")
+            )
+        )
+
+        .subcommand(App::new("hunks").visible_alias("hk")
+            .setting(AppSettings::ArgRequiredElseHelp)
+            .about("list the hunks of an executable and translate dump addresses to file offsets and back")
+            .arg(Arg::new("exe").required(true).index(1)
+                .about("AmigaOS executable (hunk format)")
+                .value_hint(ValueHint::FilePath)
+            )
+            .arg(Arg::new("dir").index(2)
+                .about("directory containing the dump, for offset and segments")
+                .value_hint(ValueHint::DirPath)
+            )
+            .arg(Arg::new("base").short('B').long("base")
+                .about("address Ghidra loaded the first hunk to (hex, default: 21F000)")
+                .takes_value(true)
+                .value_hint(ValueHint::Other)
+            )
+            .arg(Arg::new("symbols").short('y').long("symbols")
+                .about("list symbols of the hunks")
+            )
+            .arg(Arg::new("address").short('a').long("address")
+                .about("address in the dump (hex) to translate to a file offset")
+                .takes_value(true).multiple(true)
+                .value_hint(ValueHint::Other)
+            )
+            .arg(Arg::new("file-offset").short('f').long("file-offset")
+                .about("offset in the file (hex) to translate to an address in the dump")
+                .takes_value(true).multiple(true)
+                .value_hint(ValueHint::Other)
+            )
+        )
+
+        .subcommand(App::new("savestate").visible_alias("uss")
+            .setting(AppSettings::ArgRequiredElseHelp)
+            .about("print CPU, memory, ROM and custom chip state of a UAE savestate (.uss)")
+            .arg(Arg::new("file").required(true).index(1)
+                .about("savestate written by FS-UAE or WinUAE")
+                .value_hint(ValueHint::FilePath)
+            )
+            .arg(Arg::new("write").short('w').long("write")
+                .about("write the RAM as binary memory dumps (named by address) to this directory")
+                .takes_value(true)
+                .value_hint(ValueHint::DirPath)
+            )
+        )
+
+        .subcommand(App::new("memsearch").visible_alias("ms")
+            .setting(AppSettings::ArgRequiredElseHelp)
+            .about("find an unknown value by how it changes between memory dumps, in a session file")
+            .subcommand(App::new("start")
+                .about("start a session with the first memory dump")
+                .arg(Arg::new("session").required(true).index(1)
+                    .about("session file to create")
+                    .value_hint(ValueHint::FilePath)
+                )
+                .arg(Arg::new("dir").required(true).index(2)
+                    .about("directory containing the first memory dump")
+                    .value_hint(ValueHint::DirPath)
+                )
+                .arg(Arg::new("size").short('z').long("size")
+                    .about("sizes of the value in bits (default: all)")
+                    .takes_value(true).multiple(true)
+                    .possible_values(&["8", "16", "32"])
+                )
+                .arg(Arg::new("unaligned").short('u').long("unaligned")
+                    .about("also search words and longs at odd addresses (68020 and later)")
+                )
+            )
+            .subcommand(App::new("next")
+                .about("keep the candidates whose value changed as given in the next memory dump")
+                .arg(Arg::new("session").required(true).index(1)
+                    .about("session file")
+                    .value_hint(ValueHint::FilePath)
+                )
+                .arg(Arg::new("dir").required(true).index(2)
+                    .about("directory containing the next memory dump")
+                    .value_hint(ValueHint::DirPath)
+                )
+                .arg(Arg::new("relation").required(true).index(3)
+                    .about("how the value changed since the previous dump")
+                    .possible_values(&["increased", "inc", "decreased", "dec", "unchanged", "same", "changed", "equal", "eq"])
+                )
+                .arg(Arg::new("amount").index(4)
+                    .about("amount (dec) the value increased or decreased by, value it is equal to")
+                    .value_hint(ValueHint::Other)
+                )
+                .arg(Arg::new("limit").short('l').long("limit")
+                    .about("print at most this many candidates (default: 50)")
+                    .takes_value(true)
+                    .value_hint(ValueHint::Other)
+                )
+            )
+            .subcommand(App::new("show")
+                .about("print the snapshots of the session and the remaining candidates with their values")
+                .arg(Arg::new("session").required(true).index(1)
+                    .about("session file")
+                    .value_hint(ValueHint::FilePath)
+                )
+                .arg(Arg::new("limit").short('l').long("limit")
+                    .about("print at most this many candidates (default: all)")
+                    .takes_value(true)
+                    .value_hint(ValueHint::Other)
+                )
+            )
+        )
+
+        .subcommand(App::new("mem-view").visible_alias("mv")
+            .setting(AppSettings::ArgRequiredElseHelp)
+            .about("print the fields of a structure in memory, for several dumps side by side")
+            .arg(Arg::new("dir").required(true).index(1)
+                .about("directory containing the memory dump")
+                .value_hint(ValueHint::DirPath)
+            )
+            .arg(Arg::new("address").required(true).index(2)
+                .about("address of the structure (hex)")
+                .value_hint(ValueHint::Other)
+            )
+            .arg(Arg::new("struct").required(true).index(3)
+                .about("name of the structure, as described in the structs file (see help-fs)")
+                .value_hint(ValueHint::Other)
+            )
+            .arg(Arg::new("dirs").short('d').long("dirs")
+                .about("further directories with memory dumps, whose values are shown next to those of dir")
+                .takes_value(true).multiple(true)
+                .value_hint(ValueHint::DirPath)
+            )
+            .arg(Arg::new("structs").short('S').long("structs")
+                .about("file with structure descriptions (default: structs in dir or its parent)")
+                .takes_value(true)
+                .value_hint(ValueHint::FilePath)
+            )
+            .arg(Arg::new("follow").short('f').long("follow")
+                .about("also print structures that typed pointers point to, if equal in all dumps")
+            )
+        )
+
         .subcommand(App::new("help-fs").about("print info about the expected file structure"))
 }
\ No newline at end of file
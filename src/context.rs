@@ -0,0 +1,69 @@
+/*
+    Copyright (C) 2020 Arkadiusz Guzinski <kermit@ag.de1.cc>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::fmt;
+
+/// the execution context a step runs in
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Context {
+    /// user mode code, usually the program itself
+    Main,
+    /// supervisor mode outside of interrupt handlers (traps, OS, dump started in supervisor mode)
+    Supervisor,
+    /// interrupt handler with level 1-7, including everything it calls
+    Interrupt(u8),
+}
+
+impl Context {
+    pub fn is_interrupt(&self) -> bool {
+        matches!(self, Context::Interrupt(_))
+    }
+}
+
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Context::Main => write!(f, "main"),
+            Context::Supervisor => write!(f, "supervisor"),
+            Context::Interrupt(level) => write!(f, "interrupt level {}", level),
+        }
+    }
+}
+
+/// how analyses treat steps in interrupt contexts
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InterruptMode {
+    /// analyse all steps
+    Include,
+    /// ignore steps in interrupt handlers
+    Exclude,
+    /// only analyse steps in interrupt handlers
+    Only,
+    /// ignore steps in interrupt handlers, but mention each handler invocation where possible
+    Fold,
+}
+
+impl InterruptMode {
+    /// parse value of the command line option
+    pub fn from_arg(arg: &str) -> InterruptMode {
+        match arg {
+            "exclude" => InterruptMode::Exclude,
+            "only" => InterruptMode::Only,
+            "fold" => InterruptMode::Fold,
+            _ => InterruptMode::Include
+        }
+    }
+}
@@ -0,0 +1,80 @@
+/*
+    Copyright (C) 2020 Arkadiusz Guzinski <kermit@ag.de1.cc>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// sizes a 68k instruction can have, in bytes (the longest ones need a 68020 or later)
+pub const INSTRUCTION_SIZES: std::ops::RangeInclusive<u32> = 2..=22;
+
+/// executed instructions: address -> size in bytes
+pub struct Coverage {
+    pub instructions: BTreeMap<u32, u32>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage { instructions: BTreeMap::new() }
+    }
+
+    /// remove all instructions that are also in other
+    pub fn subtract(&mut self, other: &Coverage) {
+        self.instructions.retain(|pc, _| !other.instructions.contains_key(pc));
+    }
+
+    /// merge adjacent instructions to blocks of (start, size)
+    pub fn blocks(&self) -> Vec<(u32, u16)> {
+        let mut blocks: Vec<(u32, u16)> = Vec::new();
+        for (pc, size) in &self.instructions {
+            if let Some((start, len)) = blocks.last_mut() {
+                let end = *start + *len as u32;
+                if end == *pc && (*len as u32 + size) <= u16::MAX as u32 {
+                    *len += *size as u16;
+                    continue;
+                }
+            }
+            blocks.push((*pc, *size as u16));
+        }
+        blocks
+    }
+
+    /// write one address per line
+    pub fn write_list(&self, out: &mut dyn Write) -> io::Result<()> {
+        for pc in self.instructions.keys() {
+            writeln!(out, "{:08X}", pc)?;
+        }
+        Ok(())
+    }
+
+    /// write in drcov format (version 2), as read by lighthouse, dragondance and similar
+    /// coverage plugins. All addresses are relative to a single module starting at 0.
+    pub fn write_drcov(&self, module: &str, out: &mut dyn Write) -> io::Result<()> {
+        let blocks = self.blocks();
+        let end = self.instructions.iter().next_back().map(|(pc, size)| pc + size).unwrap_or(0);
+        writeln!(out, "DRCOV VERSION: 2")?;
+        writeln!(out, "DRCOV FLAVOR: drcov")?;
+        writeln!(out, "Module Table: version 2, count 1")?;
+        writeln!(out, "Columns: id, base, end, entry, checksum, timestamp, path")?;
+        writeln!(out, " 0, 0x{:08x}, 0x{:08x}, 0x{:016x}, 0x{:08x}, 0x{:08x}, {}", 0, end, 0, 0, 0, module)?;
+        writeln!(out, "BB Table: {} bbs", blocks.len())?;
+        for (start, size) in blocks {
+            out.write_all(&start.to_le_bytes())?;
+            out.write_all(&size.to_le_bytes())?;
+            out.write_all(&0u16.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
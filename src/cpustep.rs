@@ -19,6 +19,12 @@ use std::io::{self, BufRead};
 use serde::{Serialize, Deserialize};
 use crate::utils::*;
 use crate::memdump::MemDump;
+use crate::operand::{Operand, Size};
+use crate::hardware;
+
+/// condition names as used by fs-uae for Bcc, DBcc and Scc
+const CONDITIONS: [&str; 16] = ["T", "F", "HI", "LS", "CC", "CS", "NE", "EQ",
+    "VC", "VS", "PL", "MI", "GE", "LT", "GT", "LE"];
 
 // use BigArray, as this is needed to allow serde to handle arrays beyond 32 elements
 big_array! { BigArray; }
@@ -169,68 +175,196 @@ impl CpuStep {
         result
     }
 
-    /// returns change in call depth by this instruction
-    pub fn depth_mod(&self) -> i16 {
-        // ignore changes from interrupts
-        if self.imask != 0 {
-            return 0;
+    /// the instruction as disassembled by fs-uae, without trailing padding
+    pub fn instruction(&self) -> &str {
+        std::str::from_utf8(&self.note).unwrap_or_default().trim_end()
+    }
+
+    /// split instruction into mnemonic, size suffix and operand text
+    /// e.g. "CMPI.W #$0064,D0" -> ("CMPI", "W", "#$0064,D0")
+    fn split_instruction(&self) -> (&str, &str, &str) {
+        let ins = self.instruction();
+        let end = ins.find(['.', ' ']).unwrap_or(ins.len());
+        let mnemonic = &ins[..end];
+        let rest = ins[end..].trim_start();
+        if let Some(sized) = rest.strip_prefix('.') {
+            let size_end = sized.find(' ').unwrap_or(sized.len());
+            (mnemonic, &sized[..size_end], sized[size_end..].trim())
+        } else {
+            (mnemonic, "", rest.trim())
         }
-        /*
-        +1 BSR, JSR
-        -1 RTS, RTE (RTR is called RTE in log - probably only used to return from interrupt)
-         */
-        match self.note.get(0..3).unwrap_or_default() {
-            [66, 83, 82] | [74, 83, 82] => 1,
-            [82, 84, 83] | [82, 84, 69] => -1,
-            _ => 0
+    }
+
+    /// mnemonic of the instruction without size, e.g. "CMPI"
+    pub fn mnemonic(&self) -> &str {
+        self.split_instruction().0
+    }
+
+    /// operation size of the instruction
+    pub fn size(&self) -> Size {
+        Size::from_suffix(self.split_instruction().1)
+    }
+
+    /// parsed operands of the instruction
+    pub fn operands(&self) -> Vec<Operand> {
+        Operand::parse_list(self.split_instruction().2)
+    }
+
+    /// first word of the instruction, as printed by fs-uae
+    pub fn opcode(&self) -> Option<u16> {
+        let hex = std::str::from_utf8(&self.pc_note).ok()?;
+        u16::from_str_radix(hex.get(..4)?, 16).ok()
+    }
+
+    /// bytes of the instruction, as printed by fs-uae. Long instructions may be cut off.
+    pub fn bytes(&self) -> Vec<u8> {
+        std::str::from_utf8(&self.pc_note).unwrap_or_default()
+            .split_whitespace()
+            .map_while(|word| u16::from_str_radix(word, 16).ok().filter(|_| word.len() == 4))
+            .flat_map(|word| word.to_be_bytes())
+            .collect()
+    }
+
+    /// is this a Scc instruction (set according to condition)?
+    pub fn is_scc(&self) -> bool {
+        self.mnemonic().strip_prefix('S').map(|cc| CONDITIONS.contains(&cc)).unwrap_or(false)
+    }
+
+    /// memory written by the instruction: (address, size, value), with the value only known, if
+    /// it doesn't depend on memory
+    pub fn memory_write(&self) -> Option<(u32, Size, Option<u32>)> {
+        let mnemonic = self.mnemonic();
+        let size = match mnemonic {
+            "BSET" | "BCLR" | "BCHG" | "TAS" | "NBCD" => Size::Byte,
+            _ if self.is_scc() => Size::Byte,
+            _ => self.size()
+        };
+        let operands = self.operands();
+        let destination = operands.last()?;
+        if !matches!(destination, Operand::Memory { .. }) {
+            return None;
         }
+        let value = match mnemonic {
+            "MOVE" if operands.len() == 2 => operands[0].value(self, size),
+            "CLR" => Some(0),
+            "ST" => Some(0xFF),
+            "SF" => Some(0),
+            "ADD" | "ADDQ" | "ADDI" | "ADDX" | "SUB" | "SUBQ" | "SUBI" | "SUBX" | "AND" | "ANDI" | "OR" | "ORI"
+            | "EOR" | "EORI" | "NOT" | "NEG" | "NEGX" | "BSET" | "BCLR" | "BCHG" | "ASL" | "ASR" | "LSL" | "LSR"
+            | "ROL" | "ROR" | "ROXL" | "ROXR" | "TAS" | "NBCD" | "MOVEP" => None,
+            _ if self.is_scc() => None,
+            _ => return None
+        };
+        Some((destination.address(self, size)?, size, value))
     }
 
-    // pub fn depth_mod_with_interrupt(&self) -> i16 {}
-    /// print String showing PCs at call depth change
-    ///
-    /// other: instruction steps to compare with
-    /// fmt: formatting configuration
-    /// depth: current call depth. Used for padding and modified on change.
-    pub fn call_diff(&self, predecessor: &CpuStep, fmt: &FormatHelper, depth: &mut i16) {
-        let mut depth_m = predecessor.depth_mod();
-        *depth += depth_m;
-        if self.s && !predecessor.s {
-            depth_m += 1;
-            match fmt.show_interrupt {
-                Visibility::Hidden => { return; }
-                Visibility::Brief => {
-                    println!("{}Interrupt (mask={})", fmt.padding(*depth), self.imask);
-                    return;
-                }
-                Visibility::Verbose => {}
+    /// describe accesses to custom chip and CIA registers, e.g. "DMACON=SET|DMAEN" for writes
+    /// with known value or "CIAA PRA" for other accesses
+    pub fn hardware_access(&self) -> Option<String> {
+        if let Some((address, size, value)) = self.memory_write() {
+            if hardware::is_hardware(address) {
+                return match value {
+                    Some(value) => hardware::describe_write(address, size, value),
+                    None => hardware::access_name(address, size)
+                };
             }
         }
-        if depth_m <= 0 {
-            return;
+        let size = self.size();
+        let names: Vec<String> = self.operands().iter()
+            .filter_map(|o| o.address(self, size))
+            .filter(|a| hardware::is_hardware(*a))
+            .filter_map(|a| hardware::access_name(a, size))
+            .collect();
+        if names.is_empty() { None } else { Some(names.join(", ")) }
+    }
+
+    /// is this an instruction that only compares / tests and sets the condition codes?
+    pub fn is_comparison(&self) -> bool {
+        matches!(self.mnemonic(), "CMP" | "CMPA" | "CMPI" | "CMPM" | "CMP2" | "TST" | "BTST")
+    }
+
+    /// is this a conditional branch (Bcc or DBcc)?
+    pub fn is_conditional_branch(&self) -> bool {
+        let m = self.mnemonic();
+        if let Some(cc) = m.strip_prefix("DB") {
+            CONDITIONS.contains(&cc) || cc == "RA"
+        } else if let Some(cc) = m.strip_prefix('B') {
+            CONDITIONS[2..].contains(&cc) || cc == "HS" || cc == "LO"
+        } else {
+            false
+        }
+    }
+
+    /// for conditional branches: returns whether the branch was taken, next being the step
+    /// executed after this one. None if this is no conditional branch or an interrupt
+    /// occurred in between.
+    pub fn branch_taken(&self, next: &CpuStep) -> Option<bool> {
+        if !self.is_conditional_branch() || next.imask > self.imask {
+            return None;
+        }
+        Some(next.pc != self.pc_next)
+    }
+
+    /// condition codes as string in order XNZVC, '.' for cleared flags
+    pub fn ccr(&self) -> String {
+        let mut s = String::new();
+        for (set, name) in [(self.x, 'X'), (self.n, 'N'), (self.z, 'Z'), (self.v, 'V'), (self.c, 'C')].iter() {
+            s.push(if *set { *name } else { '.' });
+        }
+        s
+    }
+
+    /// describe changes of the supervisor, master and trace bits and the interrupt mask between
+    /// other and self, or None if they are unchanged
+    pub fn status_diff(&self, other: &CpuStep) -> Option<String> {
+        let mut changes: Vec<String> = Vec::new();
+        if self.s != other.s {
+            changes.push(format!("S {}->{}", other.s as u8, self.s as u8));
+        }
+        if self.m != other.m {
+            changes.push(format!("M {}->{}", other.m as u8, self.m as u8));
+        }
+        if self.t != other.t {
+            changes.push(format!("T {}->{}", other.t, self.t));
+        }
+        if self.imask != other.imask {
+            changes.push(format!("IMASK {}->{}", other.imask, self.imask));
+        }
+        if changes.is_empty() {
+            None
+        } else {
+            Some(changes.join(" "))
         }
-        println!("{}{}  from {:08X}", fmt.padding(*depth), fmt.pc(self.pc),
-                 fmt.with_offset(predecessor.pc));
-        // std::str::from_utf8(&self.note).unwrap_or_default()).as_str();
     }
 
     /// Generate String showing the difference between 2 instruction steps
     ///
     /// other: instruction steps to compare with
+    /// next: the step following self, used to tell whether a conditional branch was taken
+    /// mem: Memory dump (for printing possible content, an address register is pointing at)
+    /// fmt: formatting configuration
+    /// marker: number of steps until end pc is reached. Printed at depth changes.
+    /// depth: current call depth. Used for padding.
+    pub fn pretty_diff(&self, other: &CpuStep, next: Option<&CpuStep>, mem: &MemDump, fmt: &FormatHelper,
+                       marker: Option<usize>, depth: i16) -> String {
+        let mut s = self.changes(other, mem, fmt, depth);
+        if let Some(num) = marker {
+            s += format!("\n{}##-{:<5}", fmt.padding(depth), num).as_str();
+        }
+        s += self.instruction_line(next, fmt, depth).as_str();
+        s
+    }
+
+    /// Generate String showing register, condition code and status changes between other and
+    /// self, as well as memory referenced by the instruction of self.
+    ///
+    /// other: instruction steps to compare with
     /// mem: Memory dump (for printing possible content, an address register is pointing at)
     /// fmt: formatting configuration
-    /// num: number of steps until end pc is reached. Only printed at depth change.
-    /// depth: current call depth. Used for padding and modified on change.
-    pub fn pretty_diff(&self, other: &CpuStep, mem: &MemDump, fmt: &FormatHelper, num: usize, depth: &mut i16) -> String {
+    /// depth: current call depth. Used for padding.
+    pub fn changes(&self, other: &CpuStep, mem: &MemDump, fmt: &FormatHelper, depth: i16) -> String {
         let mut s = String::new();
-        let pad: usize = if *depth >= 0 { (*depth * fmt.indent) as usize } else { 0 };
-        // let pad_inline = if compact {0i16} else { pad };
-        let padding = format!("{:>width$}", "", width = pad);
-        let mut delimiter = String::new();
-        if fmt.compact { delimiter += "  " } else {
-            let nl = format!("\n{}", &padding);
-            delimiter += nl.as_str();
-        };
+        let delimiter = fmt.delimiter(depth);
 
         // check data registers
         let mut print_spacing = false;
@@ -243,9 +377,24 @@ impl CpuStep {
                 ).as_str();
             }
         }
+        // check condition codes
+        let (ccr, other_ccr) = (self.ccr(), other.ccr());
+        if ccr != other_ccr {
+            print_spacing = true;
+            s += format!("CCR {}->{}  ", other_ccr, ccr).as_str();
+        }
         if print_spacing {
             s += delimiter.as_str();
         }
+        // supervisor mode and interrupt mask changes are rare, but important
+        if let Some(status) = self.status_diff(other) {
+            if fmt.colors {
+                s += format!("\x1b[1;31m!! {} !!\x1b[0m", status).as_str();
+            } else {
+                s += format!("!! {} !!", status).as_str();
+            }
+            s += delimiter.as_str();
+        }
         // address registers are only parsed for certain instructions
         let note = std::str::from_utf8(&self.note).unwrap_or_default();
         let print_memory =
@@ -281,38 +430,51 @@ impl CpuStep {
                 s += delimiter.as_str();
             }
         }
-        let depth_m = self.depth_mod();
-        if depth_m > 0 || other.depth_mod() < 0 {
-            s += format!("\n{}##-{:<5}", padding, num).as_str();
-        }
-        *depth += depth_m;
+        s
+    }
+
+    /// Generate the line showing pc and instruction, annotated with the outcome of conditional
+    /// branches.
+    ///
+    /// next: the step following self
+    /// fmt: formatting configuration
+    /// depth: current call depth. Used for padding.
+    pub fn instruction_line(&self, next: Option<&CpuStep>, fmt: &FormatHelper, depth: i16) -> String {
+        let padding = fmt.padding(depth);
+        let instruction = match next.and_then(|n| self.branch_taken(n)) {
+            Some(true) => format!("{}  (taken)", self.instruction()),
+            Some(false) => format!("{}  (not taken)", self.instruction()),
+            None => self.instruction().to_string()
+        };
+        let instruction = match fmt.libs.annotate(self).or_else(|| self.hardware_access()) {
+            Some(note) => format!("{}  {}", instruction, note),
+            None => instruction
+        };
         if fmt.compact {
-            s += format!("\n{}{:08X}  {}", padding, fmt.with_offset(self.pc),
-                         std::str::from_utf8(&self.note).unwrap_or_default()).as_str();
+            format!("\n{}{:08X}  {:<64}", padding, fmt.with_offset(self.pc), instruction)
         } else {
-            s += format!("\n{}\x1b[1m{:08X}\x1b[0m  {}{}", padding, fmt.with_offset(self.pc),
-                         std::str::from_utf8(&self.note).unwrap_or_default(), delimiter).as_str()
+            format!("\n{}\x1b[1m{:08X}\x1b[0m  {:<64}{}", padding, fmt.with_offset(self.pc),
+                    instruction, fmt.delimiter(depth))
         }
-        s
     }
+}
 
-    /// print instruction in format, suitable for ghidra's instruction search feature
-    pub fn print_for_search(&self, prev: &CpuStep) -> Result<String, &str> {
-        let mut diff = (prev.pc_next - self.pc) as i32;
-        return if diff == 0 {
-            Ok(std::str::from_utf8(&self.pc_note).unwrap_or_default().trim_end().to_string())
-        } else if diff < 0 {
-            Err("Negative step not implemented")
-        } else if diff > 80 {
-            Err("Big step -- aborting now")
-        } else {
-            let mut s = String::new();
-            while diff > 0 {
-                s += "[........] ";
-                diff -= 1;
-            }
-            Ok(s.trim_end().to_string())
-        };
+#[cfg(test)]
+impl CpuStep {
+    /// step in user mode with all registers cleared but A7 = sp, executing instruction at pc
+    pub fn with_instruction(pc: u32, pc_next: u32, instruction: &str, sp: u32) -> CpuStep {
+        let mut address = [0; 8];
+        address[7] = sp;
+        CpuStep {
+            data: [0; 8], address,
+            usp: 0, isp: 0, sfc: 0, dfc: 0, cacr: 0, vbr: 0, caar: 0, msp: 0,
+            t: 0, s: false, m: false, x: false, n: false, z: false, v: false, c: false,
+            imask: 0, stp: false,
+            pc,
+            pc_note: [0x20; 24],
+            note: array_init::array_init(|i| *instruction.as_bytes().get(i).unwrap_or(&0x20)),
+            pc_next,
+        }
     }
 }
 
@@ -343,3 +505,44 @@ impl ToString for CpuStep {
                 self.pc_next)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(pc: u32, pc_next: u32, instruction: &str) -> CpuStep {
+        CpuStep::with_instruction(pc, pc_next, instruction, 0x8000)
+    }
+
+    #[test]
+    fn comparisons() {
+        for instruction in ["CMP.W D1,D0", "CMPI.L #$00000064,D0", "CMPA.L A1,A0", "CMPM.B (A0)+,(A1)+",
+            "TST.L D0", "BTST.L #$0003,D2"].iter() {
+            assert!(step(0x1000, 0x1002, instruction).is_comparison(), "{}", instruction);
+        }
+        for instruction in ["MOVE.L D0,D1", "SUB.W #$0001,D0", "BEQ.B #$04 == $0000100a (T)"].iter() {
+            assert!(!step(0x1000, 0x1002, instruction).is_comparison(), "{}", instruction);
+        }
+    }
+
+    #[test]
+    fn branches() {
+        // Z set by a CMP of equal values: BEQ is taken, BNE is not
+        let mut beq = step(0x1004, 0x1006, "BEQ.B #$04 == $0000100a (T)");
+        beq.z = true;
+        assert_eq!(beq.ccr(), "..Z..");
+        assert_eq!(beq.branch_taken(&step(0x100A, 0x100C, "NOP")), Some(true));
+        let mut bne = step(0x1004, 0x1006, "BNE.W #$0010 == $00001016 (F)");
+        bne.z = true;
+        assert_eq!(bne.branch_taken(&step(0x1006, 0x1008, "NOP")), Some(false));
+        // DBcc loops back until the counter expires
+        let dbf = step(0x1010, 0x1014, "DBF .W D1,#$fffc == $0000100e (T)");
+        assert_eq!(dbf.branch_taken(&step(0x100E, 0x1010, "NOP")), Some(true));
+        assert_eq!(dbf.branch_taken(&step(0x1014, 0x1016, "NOP")), Some(false));
+        // unconditional branches and interrupts decide nothing
+        assert_eq!(step(0x1004, 0x1006, "BRA.B #$04 == $0000100a").branch_taken(&step(0x100A, 0x100C, "NOP")), None);
+        let mut interrupt = step(0x600, 0x602, "NOP");
+        interrupt.imask = 3;
+        assert_eq!(beq.branch_taken(&interrupt), None);
+    }
+}
@@ -21,8 +21,60 @@ use std::io::{BufReader, BufWriter};
 use serde::{Serialize, Deserialize};
 use std;
 use crate::memdump::MemDump;
-use crate::utils::FormatHelper;
-use std::cmp::min;
+use crate::utils::{FormatHelper, Visibility};
+use crate::shadowstack::{ShadowStack, Frame, FrameKind};
+use crate::operand::Operand;
+use crate::callgraph::CallGraph;
+use crate::profile::Profile;
+use crate::coverage::{self, Coverage};
+use crate::hardware;
+use crate::exception::{self, ExceptionEntry, ExceptionKind};
+use crate::context::{Context, InterruptMode};
+use crate::filter::{FoldGroup, StepFilter};
+use crate::ranges::{Region, RegionMode};
+use crate::hunk::HunkFile;
+use crate::pattern::PatternChunk;
+use crate::lvo::{Libraries, LVO_OPEN_LIBRARY, LVO_OLD_OPEN_LIBRARY};
+use std::cmp::{min, max};
+
+/// maximum number of steps in one iteration of a loop, that is detected by Dump::find_loop
+const MAX_LOOP_BODY: usize = 256;
+/// maximum number of bytes above A7 searched for return addresses of callers from before the dump
+const MAX_STACK_SCAN: u32 = 4096;
+/// gaps of up to this many bytes between executed instructions are masked in search patterns,
+/// larger ones start a new chunk
+const MAX_SEARCH_GAP: u32 = 80;
+
+/// part of a summary
+enum Segment {
+    /// a single step (index)
+    Step(usize),
+    /// a loop, given as index of first step, steps per iteration and number of iterations
+    Loop(usize, usize, usize),
+    /// hidden steps, that are folded to one line: index of first step, number of steps and why
+    /// they are hidden
+    Folded(usize, usize, FoldGroup),
+}
+
+impl Segment {
+    /// index of the first step
+    fn first(&self) -> usize {
+        match self {
+            Segment::Step(i) => *i,
+            Segment::Loop(i, _, _) => *i,
+            Segment::Folded(i, _, _) => *i
+        }
+    }
+
+    /// number of instructions printed for this segment
+    fn logical_len(&self) -> usize {
+        match self {
+            Segment::Step(_) => 1,
+            Segment::Loop(_, body, _) => *body,
+            Segment::Folded(_, _, _) => 1
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize)]
 /// represents an uae instruction dump
@@ -123,10 +175,11 @@ impl Dump {
     /// val: value to search for
     /// size: expected size of value in bytes (1, 2, anything else will search 4 bytes)
     /// previous: should be result of the last call to this function
+    /// filter: steps that are searched
     ///
     /// returns: Sorted Map of pc to String describing register changes
-    pub fn search_for_register_change(&self, val: u32, size: u8, previous: Option<BTreeMap<u32, String>>)
-                                      -> BTreeMap<u32, String> {
+    pub fn search_for_register_change(&self, val: u32, size: u8, previous: Option<BTreeMap<u32, String>>,
+                                      filter: &StepFilter) -> BTreeMap<u32, String> {
         let mask: u32 = match size {
             1 => 0x000000FF,
             2 => 0x0000FFFF,
@@ -135,7 +188,7 @@ impl Dump {
 
         let mut found: BTreeMap<u32, String> = BTreeMap::new();
         for cs in self.singles.values() {
-            found.extend(self.search_for_register_change_from(*cs, val, mask));
+            found.extend(self.search_for_register_change_from(*cs, val, mask, filter));
         }
 
         match previous {
@@ -158,50 +211,107 @@ impl Dump {
     /// start: start at steps[start]
     /// val: value to look for
     /// mask: bitmask for value (all saved values are 32 bit)
+    /// filter: steps that are searched. Hidden steps are skipped, changes are found by comparing
+    ///     with the last step that was not hidden.
     ///
     /// returns: Map of pc, description of change (e.g. ", D0: 15 -> 14")
-    fn search_for_register_change_from(&self, start: usize, val: u32, mask: u32)
+    fn search_for_register_change_from(&self, start: usize, val: u32, mask: u32, filter: &StepFilter)
                                            -> BTreeMap<u32, String> {
         // maximum of instructions to search
-        let mut to_go = 10000;
-        let mut index = start;
+        let end = min(start + 10000, self.steps.len().saturating_sub(1));
         // we track depth, so we can return when reaching the function, that called the one at start
-        let mut depth: i16 = 0;
-        let mut last: &CpuStep = self.steps.get(index).unwrap();
+        let mut shadow = ShadowStack::new(&self.steps[start]);
+        let mut last: &CpuStep = &self.steps[start];
         let mut found: BTreeMap<u32, String> = BTreeMap::new();
-        loop {
-            index += 1;
-            match self.steps.get(index + 1) {
-                Some(current) => {
-                    // println!("{}", current.to_string());
-                    let mut res = current.register_changed_to(last, val, mask);
-                    let mut idx = 0;
-                    if res != 0 {
-                        let mut s = String::new();
-                        while res != 0 {
-                            if res & 1 == 1 {
-                                s += format!(", @{} D{}: {:x} -> {:x} ", index, idx,
-                                             last.data[idx], current.data[idx]).as_str();
-                            }
-                            res /= 2;
-                            idx += 1;
-                            // println!("{}", current.to_string());
+        for index in start..end {
+            let current = &self.steps[index + 1];
+            shadow.update(index, &self.steps[index], current);
+            if filter.shows(index + 1) {
+                let mut res = current.register_changed_to(last, val, mask);
+                let mut idx = 0;
+                if res != 0 {
+                    let mut s = String::new();
+                    while res != 0 {
+                        if res & 1 == 1 {
+                            s += format!(", @{} D{}: {:x} -> {:x} ", index, idx,
+                                         last.data[idx], current.data[idx]).as_str();
                         }
-                        found.insert(current.pc, s);
-                    }
-                    last = current;
-                    to_go -= 1;
-                    depth = depth + current.depth_mod();
-                    if depth < 0 || to_go <= 0 {
-                        break;
+                        res /= 2;
+                        idx += 1;
                     }
+                    found.insert(current.pc, s);
                 }
-                None => break
+                last = current;
+            }
+            if shadow.depth(current) < 0 {
+                break;
             }
         }
         found
     }
 
+    /// print comparisons (CMP, CMPA, CMPI, CMPM, TST, BTST) where an operand had the value val,
+    /// together with the resulting condition codes and the outcome of the following conditional
+    /// branch.
+    ///
+    /// Operands are matched by register contents at the time of the comparison and immediate
+    /// values, both masked to the size of the operation. Memory operands are not resolved.
+    ///
+    /// val: value to search for
+    /// fmt: contains formatting options
+    pub fn comparisons(&self, val: u32, fmt: FormatHelper) {
+        let filter = self.step_filter(&fmt);
+        for idx in 0..self.steps.len().saturating_sub(1) {
+            let step = &self.steps[idx];
+            if !step.is_comparison() || !filter.shows(idx) {
+                continue;
+            }
+            let size = step.size();
+            if val & size.mask() != val {
+                continue;
+            }
+            let matching: Vec<String> = step.operands().iter()
+                .filter(|o| o.value(step, size) == Some(val))
+                .map(|o| match o {
+                    Operand::Immediate(_) => o.to_string(),
+                    _ => format!("{}={:X}", o, val)
+                })
+                .collect();
+            if matching.is_empty() {
+                continue;
+            }
+            let branch = match self.following_branch(idx) {
+                Some((b, taken)) => format!("  -> @{} {} {}", b, self.steps[b].instruction(),
+                                            if taken { "(taken)" } else { "(not taken)" }),
+                None => String::new()
+            };
+            println!("{}, @{}  {}  [{}]  {}{}", fmt.pc(step.pc), idx, step.instruction(),
+                     matching.join(" "), self.steps[idx + 1].ccr(), branch);
+        }
+    }
+
+    /// find the conditional branch that evaluates the condition codes set by steps[idx]
+    ///
+    /// returns: index of the branch and whether it was taken, or None if the condition codes are
+    /// changed or a call, return or exception happens first.
+    fn following_branch(&self, idx: usize) -> Option<(usize, bool)> {
+        let max = min(idx + 8, self.steps.len() - 1);
+        let mut shadow = ShadowStack::new(&self.steps[idx + 1]);
+        for i in idx + 1..max {
+            let step = &self.steps[i];
+            if let Some(taken) = step.branch_taken(&self.steps[i + 1]) {
+                return Some((i, taken));
+            }
+            let next = &self.steps[i + 1];
+            let change = shadow.update(i, step, next);
+            if step.is_comparison() || change.entered.is_some() || !change.left.is_empty()
+                || step.ccr() != next.ccr() {
+                return None;
+            }
+        }
+        None
+    }
+
     /// finds first index of pc in self.steps
     fn first_index_of_pc(&self, pc: u32) -> Result<usize, &str> {
         for idx in 0..self.steps.len() {
@@ -255,114 +365,667 @@ impl Dump {
 
     /// prints a summary of instructions and data changes, leading to pc
     ///
+    /// Loops are collapsed to their first iteration (see find_loop), if enabled in fmt. Steps in
+    /// interrupt handlers are skipped or folded to one line, depending on fmt.interrupts.
+    ///
     /// mem: MemDump, that can (partially) resolve references in address registers (can be empty)
     /// pc: program counter at which to start (first occurrence in dump will be used)
-    /// num_before: print a maximum of num_before instructions prior to pc
+    /// num_before: print a maximum of num_before instructions prior to pc. A collapsed loop counts
+    ///     as the instructions of one iteration.
     /// fmt: contains formatting options
     pub fn inspect(&self, mem: MemDump, pc: u32, num_before: usize, fmt: FormatHelper) -> Result<(), &str> {
         // general preparation
         let end = self.first_index_of_pc(pc)?;
-        let start = if end > num_before { end - num_before } else { 0 } + 1;
-        let mut current = self.steps.get(start).expect("cpu step not found");
+        let filter = self.step_filter(&fmt);
+        // grow the range of steps until it contains enough logical steps
+        let mut window = max(num_before, 1);
+        let segments = loop {
+            let start = end.saturating_sub(window) + 1;
+            let mut segments = self.segments(start, end, fmt.collapse_loops, &filter);
+            let mut logical: usize = segments.iter().map(|s| s.logical_len()).sum();
+            if logical >= num_before || start == 1 {
+                let mut skip = 0;
+                while logical > num_before && skip + 1 < segments.len() {
+                    logical -= segments[skip].logical_len();
+                    skip += 1;
+                }
+                segments.drain(..skip);
+                break segments;
+            }
+            window *= 2;
+        };
+        let start = match segments.first() {
+            Some(segment) => segment.first(),
+            None => return Ok(())
+        };
         // get base depth
-        let mut depth: i16 = 0;
-        let mut min_depth: i16 = 0;
-        for i in start..=end {
-            depth += self.steps.get(i).expect("cpu step not found").depth_mod();
-            min_depth = min(min_depth, depth);
-        }
-        depth = 0 - min_depth;
-        for i in start..=end {
-            let last = current;
-            current = self.steps.get(i).expect("cpu step not found");
-            print!("{}", current.pretty_diff(&last, &mem, &fmt, end - i, &mut depth));
+        let mut depths = self.depths(end + 1);
+        let base = depths[start..].iter().min().copied().unwrap_or(0);
+        for d in depths.iter_mut() {
+            *d -= base;
+        }
+        // mark calls and returns with the distance to end
+        let marker = |i: usize, last: usize| {
+            if depths.get(i + 1).map(|d| *d > depths[i]).unwrap_or(false) && filter.shows(i + 1)
+                || depths[i] < depths[last] {
+                Some(end - i)
+            } else {
+                None
+            }
+        };
+        let mut last = start;
+        for segment in segments {
+            match segment {
+                Segment::Step(i) => {
+                    let current = self.steps.get(i).expect("cpu step not found");
+                    print!("{}", current.pretty_diff(&self.steps[last], self.steps.get(i + 1), &mem, &fmt,
+                                                     marker(i, last), depths[i]));
+                    last = i;
+                }
+                Segment::Loop(first, body, iterations) => {
+                    print!("{}", self.steps[first].changes(&self.steps[last], &mem, &fmt, depths[first]));
+                    print!("\n{}[loop: {} iterations of {} steps{}]", fmt.padding(depths[first]), iterations, body,
+                           self.loop_changes(first, body, iterations, &fmt));
+                    for (step, depth) in self.steps[first..first + body].iter().zip(&depths[first..]) {
+                        print!("{}", step.instruction_line(None, &fmt, *depth));
+                    }
+                    last = first + body * iterations - 1;
+                }
+                Segment::Folded(first, len, group) => {
+                    let text = filter.describe_fold(group, self.steps[first].pc, len, &fmt);
+                    if fmt.compact {
+                        print!("\n{}{:<74}", fmt.padding(depths[first]), text);
+                    } else {
+                        print!("\n{}{}{}", fmt.padding(depths[first]), text, fmt.delimiter(depths[first]));
+                    }
+                }
+            }
         }
         Ok(())
     }
 
-    /// print a string that can be pasted into ghidra's instruction search (hex mode)
+    /// split steps start..=end into single steps, loops and folded interrupt handlers
+    ///
+    /// collapse: if false, no loops are detected
+    /// filter: steps it hides are left out, or folded to one segment per run
+    fn segments(&self, start: usize, end: usize, collapse: bool, filter: &StepFilter) -> Vec<Segment> {
+        let mut segments = Vec::new();
+        let mut i = start;
+        while i <= end {
+            if !filter.shows(i) {
+                let hidden = filter.hidden_run(i, end + 1);
+                if let Some(group) = filter.fold_group(i) {
+                    segments.push(Segment::Folded(i, hidden, group));
+                }
+                i += hidden;
+                continue;
+            }
+            if collapse {
+                if let Some((body, iterations)) = self.find_loop(i, end) {
+                    segments.push(Segment::Loop(i, body, iterations));
+                    i += body * iterations;
+                    continue;
+                }
+            }
+            segments.push(Segment::Step(i));
+            i += 1;
+        }
+        segments
+    }
+
+    /// detect a loop beginning at steps[start] and ending at or before steps[end]. A loop is a
+    /// sequence of pcs that ends with a jump back to its first pc (e.g. by DBcc or a backward
+    /// Bcc) and is repeated at least once.
     ///
-    /// pc: instruction to start at
-    /// num_after: include this many following instructions
-    pub fn ghidra_search(&self, pc: u32, num_after: usize) -> Result<(), &str> {
+    /// returns: number of steps per iteration and number of complete iterations
+    fn find_loop(&self, start: usize, end: usize) -> Option<(usize, usize)> {
+        let pc = self.steps[start].pc;
+        let max_i = min(start + MAX_LOOP_BODY, end);
+        let body = (start + 1..=max_i).find(|i| self.steps[*i].pc == pc)? - start;
+        // the last step of the body must jump backwards
+        if self.steps[start + body - 1].pc < pc {
+            return None;
+        }
+        let mut iterations = 1;
+        while start + (iterations + 1) * body - 1 <= end
+            && (0..body).all(|k| self.steps[start + iterations * body + k].pc == self.steps[start + k].pc) {
+            iterations += 1;
+        }
+        if iterations > 1 { Some((body, iterations)) } else { None }
+    }
+
+    /// describe registers that changed across the iterations of a loop, with their value at the
+    /// beginning of the first iteration and after the last one
+    fn loop_changes(&self, start: usize, body: usize, iterations: usize, fmt: &FormatHelper) -> String {
+        let after_idx = start + body * iterations;
+        let after = self.steps.get(after_idx).unwrap_or(&self.steps[after_idx - 1]);
+        let mut s = String::new();
+        for r in 0..16 {
+            let value = |step: &CpuStep| if r < 8 { step.data[r] } else { step.address[r - 8] };
+            let first = value(&self.steps[start]);
+            if value(after) != first || (1..iterations).any(|k| value(&self.steps[start + k * body]) != first) {
+                s += format!("  {}{} {}->{}", if r < 8 { 'D' } else { 'A' }, r % 8,
+                             fmt.col_reg(first), fmt.col_reg(value(after))).as_str();
+            }
+        }
+        s
+    }
+
+    /// instruction patterns for Ghidra's instruction search for the code executed from the first
+    /// occurrence of pc on. The executed path is followed across branches and calls until
+    /// num_after distinct instructions of the program (not ROM or OS) are collected. They are
+    /// grouped into one chunk per contiguous run of addresses, small gaps are masked.
+    ///
+    /// Longs in extension words are masked if they are relocated (relocs: executable and address
+    /// of each hunk in the dump) or look like runtime addresses: inside a segment or in a 64K
+    /// block (except the first) containing executed program code.
+    pub fn ghidra_search(&self, pc: u32, num_after: usize, fmt: &FormatHelper,
+                         relocs: Option<(&HunkFile, &[u32])>) -> Result<Vec<PatternChunk>, &str> {
         let start = self.first_index_of_pc(pc)?;
-        let end = start + num_after;
-        let mut current = self.steps.get(start).expect("cpu step not found");
-
-        for i in start..=end {
-            let last = current;
-            current = self.steps.get(i).expect("cpu step not found");
-            match current.print_for_search(&last) {
-                Ok(s) => println!("{}", s),
-                Err(e) => {
-                    println!("{}", e);
-                    return Ok(());
+        let program = |pc: u32| !fmt.ranges.region_of(pc).is_system();
+        let mut code: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+        for step in self.steps[start..].iter().filter(|s| program(s.pc)) {
+            if code.len() >= num_after {
+                break;
+            }
+            code.entry(step.pc).or_insert_with(|| step.bytes());
+        }
+        let blocks: BTreeSet<u32> = self.steps.iter().filter(|s| program(s.pc)).map(|s| s.pc >> 16).collect();
+        let is_address = |value: u32| fmt.segments.segment_of(value).is_some()
+            || (value > 0xFFFF && program(value) && blocks.contains(&(value >> 16)));
+
+        let mut chunks: Vec<PatternChunk> = Vec::new();
+        for (pc, bytes) in code {
+            let mut pattern: Vec<Option<u8>> = bytes.iter().map(|b| Some(*b)).collect();
+            // the opcode word is never masked
+            for k in (2..bytes.len().saturating_sub(3)).step_by(2) {
+                let value = u32::from_be_bytes([bytes[k], bytes[k + 1], bytes[k + 2], bytes[k + 3]]);
+                let relocated = relocs.is_some_and(|(exe, starts)| exe.is_relocated_at(starts, pc + k as u32));
+                if relocated || is_address(value) {
+                    pattern[k..k + 4].iter_mut().for_each(|b| *b = None);
                 }
             }
+            match chunks.last_mut() {
+                Some(chunk) if pc >= chunk.end() && pc - chunk.end() <= MAX_SEARCH_GAP =>
+                    chunk.instructions.push((pc, pattern)),
+                _ => chunks.push(PatternChunk { instructions: vec![(pc, pattern)] })
+            }
         }
-        Ok(())
+        Ok(chunks)
     }
 
     /// print call hierarchy, leading to pc
     ///
     /// pc: program counter at the bottom of the hierarchy (first occurrence in dump will be used)
     /// fmt: contains formatting options
-    pub fn stack(&self, pc: u32, fmt: FormatHelper) -> Result<(), &str> {
-        let mut idx = self.first_index_of_pc(pc)?;
-        let mut depth: i16 = 0;
-        let mut min_depth: i16 = 0;
-        let mut current = self.steps.get(idx).expect("cpu step not found");
-        let mut lines: Vec<String> = Vec::new();
-        lines.push(format!("{:08X}  {}", current.pc - fmt.offset_mod,
-                           std::str::from_utf8(&current.note).unwrap_or_default()));
-
-        loop {
-            let last = current;
-            current = self.steps.get(idx).expect("cpu step not found");
-            depth -= current.depth_mod();
-            if depth < min_depth {
-                lines.push(format!("{:08X}  {}", last.pc - fmt.offset_mod,
-                                   std::str::from_utf8(&last.note).unwrap_or_default()));
-                min_depth = depth;
-            }
-            if idx == 0 {
-                break;
+    /// mem: memory dump containing the stack, used to recover callers from before the dump
+    pub fn stack(&self, pc: u32, mem: &MemDump, fmt: FormatHelper) -> Result<(), &str> {
+        let idx = self.first_index_of_pc(pc)?;
+        let mut shadow = self.shadow_stack(mem, &fmt);
+        for i in 0..idx {
+            shadow.update(i, &self.steps[i], &self.steps[i + 1]);
+        }
+        let current = &self.steps[idx];
+        for frame in shadow.chain(current) {
+            let caller = &self.steps[frame.index];
+            match frame.kind {
+                FrameKind::Root => continue,
+                FrameKind::Outer => println!("{:08X}  <return address, called before dump>  {}",
+                                             fmt.with_offset(frame.return_address), fmt.pc(frame.entry)),
+                FrameKind::Call => match fmt.libs.annotate(caller) {
+                    Some(call) => println!("{:08X}  {:<64}  {}", fmt.with_offset(caller.pc), caller.instruction(), call),
+                    None => println!("{:08X}  {}", fmt.with_offset(caller.pc),
+                                     std::str::from_utf8(&caller.note).unwrap_or_default())
+                },
+                FrameKind::Exception => println!("{:08X}  {}  -> {}", fmt.with_offset(caller.pc), caller.instruction(),
+                                                 exception::describe(caller, &self.steps[frame.index + 1], mem))
+            }
+        }
+        println!("{:08X}  {}", fmt.with_offset(current.pc),
+                 std::str::from_utf8(&current.note).unwrap_or_default());
+        Ok(())
+    }
+
+    /// shadow stack for the start of the dump, including callers recovered from mem
+    fn shadow_stack(&self, mem: &MemDump, fmt: &FormatHelper) -> ShadowStack {
+        let first = self.steps.first().expect("cpu step not found");
+        let mut shadow = ShadowStack::new(first);
+        shadow.push_outer(self.outer_frames(mem, fmt));
+        shadow
+    }
+
+    /// recover callers that were active before the dump started, outermost first, by scanning
+    /// the stack above A7 of the first step for plausible return addresses: addresses following
+    /// an executed or dumped JSR/BSR. Known functions only name the entry of a frame, an address
+    /// inside one alone is no evidence of a call (pointers to code are common on the stack).
+    fn outer_frames(&self, mem: &MemDump, fmt: &FormatHelper) -> Vec<Frame> {
+        let first = match self.steps.first() {
+            Some(first) => first,
+            None => return Vec::new()
+        };
+        let after_call: BTreeSet<u32> = self.steps.iter()
+            .filter(|s| matches!(s.mnemonic(), "JSR" | "BSR"))
+            .map(|s| s.pc_next)
+            .collect();
+        let mut frames = Vec::new();
+        let sp = first.address[7];
+        for offset in (0..MAX_STACK_SCAN).step_by(2) {
+            let addr = sp.wrapping_add(offset);
+            let value = match mem.long_at(addr) {
+                Some(value) => value,
+                None => break
+            };
+            if value == 0 || value & 1 != 0 {
+                continue;
+            }
+            if after_call.contains(&value) || Dump::follows_call(mem, value) {
+                frames.push(Frame {
+                    kind: FrameKind::Outer,
+                    index: 0,
+                    entry: fmt.function_start(value).unwrap_or(value),
+                    sp: addr,
+                    return_address: value,
+                });
             }
-            idx -= 1;
         }
+        frames.reverse();
+        frames
+    }
 
-        for line in lines.iter().rev() {
-            println!("{}", line);
+    /// does the code in mem right before address contain a JSR or BSR, ending at address?
+    fn follows_call(mem: &MemDump, address: u32) -> bool {
+        let word = |len: u32| mem.word_at(address.wrapping_sub(len)).unwrap_or(0);
+        // JSR (An), BSR.B
+        let w = word(2);
+        if w & 0xfff8 == 0x4e90 || (w & 0xff00 == 0x6100 && w & 0xff != 0 && w & 0xff != 0xff) {
+            return true;
+        }
+        // JSR d16(An), JSR d8(An,Xn), JSR abs.W, JSR d16(PC), JSR d8(PC,Xn), BSR.W
+        let w = word(4);
+        if (0x4ea8..=0x4ebb).contains(&w) && w != 0x4eb9 || w == 0x6100 {
+            return true;
+        }
+        // JSR abs.L, BSR.L
+        let w = word(6);
+        w == 0x4eb9 || w == 0x61ff
+    }
+
+    /// call depth of steps 0..=end, as tracked by the shadow stack. The depth of the function
+    /// active at the start of the dump is 0.
+    fn depths(&self, end: usize) -> Vec<i16> {
+        let mut depths = Vec::with_capacity(end + 1);
+        if self.steps.is_empty() {
+            return depths;
+        }
+        let mut shadow = ShadowStack::new(&self.steps[0]);
+        depths.push(0);
+        for i in 0..min(end, self.steps.len() - 1) {
+            shadow.update(i, &self.steps[i], &self.steps[i + 1]);
+            depths.push(shadow.depth(&self.steps[i + 1]));
+        }
+        depths
+    }
+
+    /// execution context of every step. Inside exception handlers, the innermost handler decides
+    /// between interrupt and supervisor context, outside of them the supervisor bit.
+    pub fn contexts(&self) -> Vec<Context> {
+        let mut contexts = Vec::with_capacity(self.steps.len());
+        let mut shadow = match self.steps.first() {
+            Some(first) => ShadowStack::new(first),
+            None => return contexts
+        };
+        // interrupt level by index of the interrupted step
+        let mut levels: HashMap<usize, u8> = HashMap::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            let handler = shadow.chain(step).into_iter().rev()
+                .find(|f| f.kind == FrameKind::Exception)
+                .map(|f| levels.get(&f.index).copied());
+            contexts.push(match handler {
+                Some(Some(level)) => Context::Interrupt(level),
+                Some(None) => Context::Supervisor,
+                None if step.s => Context::Supervisor,
+                None => Context::Main
+            });
+            let next = match self.steps.get(i + 1) {
+                Some(next) => next,
+                None => break
+            };
+            if let Some(Frame { kind: FrameKind::Exception, index, .. }) = shadow.update(i, step, next).entered {
+                if let ExceptionKind::Interrupt(level) = ExceptionKind::classify(step, next, None) {
+                    levels.insert(index, level);
+                }
+            }
+        }
+        contexts
+    }
+
+    /// filter for the steps to analyse according to fmt.interrupts and fmt.regions. Contexts and
+    /// regions are only determined if needed.
+    pub fn step_filter(&self, fmt: &FormatHelper) -> StepFilter {
+        let contexts = match fmt.interrupts {
+            InterruptMode::Include => Vec::new(),
+            _ => self.contexts()
+        };
+        let regions: Vec<Region> = match fmt.regions {
+            RegionMode::Include => Vec::new(),
+            _ => self.steps.iter().map(|s| fmt.ranges.region_of(s.pc)).collect()
+        };
+        StepFilter::new(fmt.interrupts, contexts, fmt.regions, regions)
+    }
+
+    /// find the bases of known libraries: exec from address 4, others by the library name in
+    /// mem and from the results of OpenLibrary calls in the dump
+    pub fn find_libraries(&self, mem: &MemDump, libs: &mut Libraries) {
+        if let Some(exec) = mem.long_at(4) {
+            libs.add_base(exec, "exec");
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            let offset = match Libraries::call_offset(step) {
+                Some(offset) => offset,
+                None => continue
+            };
+            let base = step.address[6];
+            if !libs.bases.contains_key(&base) {
+                // struct Library starts with a Node, ln_Name is at offset 10
+                if let Some(name) = mem.long_at(base.wrapping_add(10)).and_then(|a| mem.string_at(a, 64)) {
+                    libs.add_base(base, &name);
+                }
+            }
+            let is_exec = libs.bases.get(&base).map(|l| libs.libs[*l].name == "exec").unwrap_or(false);
+            if is_exec && (offset == LVO_OPEN_LIBRARY || offset == LVO_OLD_OPEN_LIBRARY) {
+                let name = match mem.string_at(step.address[1], 64) {
+                    Some(name) => name,
+                    None => continue
+                };
+                // the result is in D0, when returning to the caller
+                if let Some(ret) = self.steps[i + 1..].iter()
+                    .find(|s| s.pc == step.pc_next && s.address[7] == step.address[7]) {
+                    if ret.data[0] != 0 {
+                        libs.add_base(ret.data[0], &name);
+                    }
+                }
+            }
         }
-        Ok(())
     }
 
     /// print full call tree
     ///
     /// fmt: contains formatting options
-    pub fn calls(&self, fmt: FormatHelper) -> Result<(), &str> {
-        // general preparation
-        let mut current = self.steps.first().expect("cpu step not found");
+    /// mem: memory dump containing the stack, used to recover callers from before the dump
+    pub fn calls(&self, mem: &MemDump, fmt: FormatHelper) -> Result<(), &str> {
+        let mut shadow = self.shadow_stack(mem, &fmt);
+        let first = &self.steps[0];
+        let outer: Vec<Frame> = shadow.chain(first).into_iter()
+            .filter(|f| f.kind == FrameKind::Outer)
+            .cloned()
+            .collect();
+
+        let filter = self.step_filter(&fmt);
         // get base depth
-        let mut depth: i16 = 0;
-        let mut min_depth: i16 = 0;
-        for i in 0..self.steps.len() {
-            depth += self.steps.get(i).expect("cpu step not found").depth_mod();
-            min_depth = min(min_depth, depth);
-        }
-        depth = 0 - min_depth;
+        let depths = self.depths(self.steps.len());
+        let base = min(depths.iter().min().copied().unwrap_or(0), -(outer.len() as i16));
 
         // do output
-        for i in 1..self.steps.len() {
-            let last = current;
-            current = self.steps.get(i).expect("cpu step not found");
-            // println!("{:08X}  {}", last.pc - fmt.offset_mod,
-            //                    std::str::from_utf8(&last.note).unwrap_or_default()));
-            current.call_diff(&last, &fmt, &mut depth);
+        for (k, frame) in outer.iter().enumerate() {
+            println!("{}{}  (before dump, returns to {:08X})", fmt.padding(k as i16 - outer.len() as i16 - base),
+                     fmt.pc(frame.entry), fmt.with_offset(frame.return_address));
+        }
+        if !outer.is_empty() {
+            println!("{}{}  (start of dump)", fmt.padding(-base), fmt.pc(first.pc));
+        }
+        for (prev, pair) in self.steps.windows(2).enumerate() {
+            let (i, last, current) = (prev + 1, &pair[0], &pair[1]);
+            let change = shadow.update(prev, last, current);
+            let depth = depths[i] - base;
+            if !filter.shows(i) {
+                match filter.fold_group(i) {
+                    Some(group) if filter.shows(prev) || filter.fold_group(prev) != Some(group) => {
+                        // interrupts are printed at the depth of the interrupted function, like exceptions
+                        let indent = if let FoldGroup::Context(_) = group { depth - 1 } else { depth };
+                        println!("{}{}", fmt.padding(indent), filter.describe_fold(
+                            group, current.pc, filter.hidden_run(i, self.steps.len()), &fmt));
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            match change.entered {
+                Some(Frame { kind: FrameKind::Exception, .. }) => match fmt.show_interrupt {
+                    Visibility::Hidden => {}
+                    Visibility::Brief => println!("{}{}", fmt.padding(depth - 1), exception::describe(last, current, mem)),
+                    Visibility::Verbose => println!("{}{}  {}  from {:08X}", fmt.padding(depth - 1),
+                                                    exception::describe(last, current, mem), fmt.pc(current.pc),
+                                                    fmt.with_offset(last.pc))
+                },
+                Some(_) if fmt.show_interrupt == Visibility::Verbose || !shadow.in_exception(current) =>
+                    match fmt.libs.annotate(last) {
+                        Some(call) => println!("{}{}  from {:08X}  {}", fmt.padding(depth), fmt.pc(current.pc),
+                                               fmt.with_offset(last.pc), call),
+                        None => println!("{}{}  from {:08X}", fmt.padding(depth), fmt.pc(current.pc),
+                                         fmt.with_offset(last.pc))
+                    },
+                _ => {}
+            }
         }
         Ok(())
     }
 
+    /// aggregate calls to a graph of functions, identified by their entry pc
+    ///
+    /// Functions that were already active at the beginning of the dump are identified by the
+    /// first pc seen in them, the edges to them have no calls. Steps and calls hidden by
+    /// fmt.interrupts are not counted.
+    pub fn call_graph(&self, fmt: &FormatHelper) -> CallGraph {
+        let filter = self.step_filter(fmt);
+        let mut graph = CallGraph::new();
+        let mut shadow = match self.steps.first() {
+            Some(first) => ShadowStack::new(first),
+            None => return graph
+        };
+        let mut entry = self.steps[0].pc;
+        for (i, step) in self.steps.iter().enumerate() {
+            if filter.shows(i) {
+                graph.add_instructions(entry, 1);
+            }
+            let next = match self.steps.get(i + 1) {
+                Some(next) => next,
+                None => break
+            };
+            let change = shadow.update(i, step, next);
+            if change.entered.is_none() && change.left.is_empty() && step.s == next.s {
+                continue;
+            }
+            let chain: Vec<u32> = shadow.chain(next).iter().map(|f| f.entry).collect();
+            entry = *chain.last().expect("call stack empty");
+            let caller = if change.entered.is_some() { chain[chain.len() - 2] } else { entry };
+            // left frames are innermost first, so each one was called by the following one
+            for (k, frame) in change.left.iter().enumerate() {
+                let frame_caller = change.left.get(k + 1).map(|f| f.entry).unwrap_or(caller);
+                let first = if frame.kind == FrameKind::Root { frame.index } else { frame.index + 1 };
+                if !filter.shows(first) {
+                    continue;
+                }
+                // a function that was active before the dump started was not called in it, only
+                // its steps are counted
+                graph.add_inclusive(frame_caller, frame.entry, i + 1 - first);
+            }
+            match change.entered {
+                Some(frame) if filter.shows(i + 1) => graph.add_call(caller, frame.entry),
+                _ => {}
+            }
+        }
+        // functions that did not return before the end of the dump
+        let chain = shadow.chain(self.steps.last().unwrap());
+        for k in (1..chain.len()).filter(|k| filter.shows(chain[*k].index + 1)) {
+            graph.add_inclusive(chain[k - 1].entry, chain[k].entry, self.steps.len() - chain[k].index - 1);
+        }
+        graph
+    }
+
+    /// count instructions executed per call stack, tracking calls like calls()
+    ///
+    /// Functions that were already active at the beginning of the dump are identified by the
+    /// first pc seen in them. Steps hidden by fmt.interrupts are not counted.
+    pub fn profile(&self, fmt: &FormatHelper) -> Profile {
+        let filter = self.step_filter(fmt);
+        let mut profile = Profile::new();
+        let mut shadow = match self.steps.first() {
+            Some(first) => ShadowStack::new(first),
+            None => return profile
+        };
+        let mut stack: Vec<u32> = vec![self.steps[0].pc];
+        // steps are counted in runs with the same call stack
+        let mut run = 0;
+        for (i, step) in self.steps.iter().enumerate() {
+            if filter.shows(i) {
+                run += 1;
+            }
+            let next = match self.steps.get(i + 1) {
+                Some(next) => next,
+                None => break
+            };
+            let change = shadow.update(i, step, next);
+            if change.entered.is_none() && change.left.is_empty() && step.s == next.s {
+                continue;
+            }
+            let new_stack: Vec<u32> = shadow.chain(next).iter().map(|f| f.entry).collect();
+            if new_stack != stack {
+                profile.add(&stack, run);
+                run = 0;
+                stack = new_stack;
+            }
+        }
+        profile.add(&stack, run);
+        profile
+    }
+
+    /// all exceptions with their kind, handler and the point they returned to
+    ///
+    /// mem: memory dump containing the vector table (at VBR)
+    fn exception_entries(&self, mem: &MemDump) -> Vec<ExceptionEntry> {
+        let mut shadow = match self.steps.first() {
+            Some(first) => ShadowStack::new(first),
+            None => return Vec::new()
+        };
+        let mut entries: Vec<ExceptionEntry> = Vec::new();
+        for i in 0..self.steps.len() - 1 {
+            let (step, next) = (&self.steps[i], &self.steps[i + 1]);
+            let change = shadow.update(i, step, next);
+            for frame in change.left.iter().filter(|f| f.kind == FrameKind::Exception) {
+                if let Some(entry) = entries.iter_mut().rev().find(|e| e.index == frame.index && e.returned.is_none()) {
+                    entry.returned = Some((i + 1, next.pc));
+                }
+            }
+            if let Some(Frame { kind: FrameKind::Exception, .. }) = change.entered {
+                entries.push(ExceptionEntry { index: i, kind: exception::describe(step, next, mem), handler: next.pc,
+                    returned: None });
+            }
+        }
+        entries
+    }
+
+    /// print every exception with its kind, vector, handler and the point it returned to
+    ///
+    /// mem: memory dump containing the vector table (at VBR)
+    pub fn exceptions(&self, mem: &MemDump, fmt: &FormatHelper) {
+        let filter = self.step_filter(fmt);
+        println!("Index\tPC\tException\tVector\tHandler\tReturn");
+        for entry in self.exception_entries(mem).into_iter().filter(|e| filter.shows(e.index + 1)) {
+            let vector = entry.kind.vector().map(|v| format!("${:03X}", v as u32 * 4)).unwrap_or_else(|| "?".to_string());
+            let ret = match entry.returned {
+                Some((idx, pc)) => format!("{:08X} @{}", fmt.with_offset(pc), idx),
+                None => "-".to_string()
+            };
+            println!("{}\t{:08X}\t{}\t{}\t{}\t{}", entry.index, fmt.with_offset(self.steps[entry.index].pc),
+                     entry.kind, vector, fmt.pc(entry.handler), ret);
+        }
+    }
+
+    /// print every interrupt handler invocation with its duration in steps, followed by totals
+    /// per interrupt level. Nested interrupts are counted in the duration of the interrupted
+    /// handler, too.
+    ///
+    /// mem: memory dump containing the vector table (at VBR)
+    pub fn interrupts(&self, mem: &MemDump, fmt: &FormatHelper) {
+        // level -> (kind, invocations, steps, longest)
+        let mut totals: BTreeMap<u8, (ExceptionKind, usize, usize, usize)> = BTreeMap::new();
+        println!("Index\tPC\tInterrupt\tHandler\tSteps\tReturn");
+        for entry in self.exception_entries(mem) {
+            let level = match entry.kind {
+                ExceptionKind::Interrupt(level) => level,
+                _ => continue
+            };
+            let (steps, ret) = match entry.returned {
+                Some((idx, pc)) => (idx - entry.index - 1, format!("{:08X} @{}", fmt.with_offset(pc), idx)),
+                // still running at the end of the dump
+                None => (self.steps.len() - entry.index - 1, "-".to_string())
+            };
+            println!("{}\t{:08X}\t{}\t{}\t{}\t{}", entry.index, fmt.with_offset(self.steps[entry.index].pc),
+                     entry.kind, fmt.pc(entry.handler), steps, ret);
+            let total = totals.entry(level).or_insert((entry.kind, 0, 0, 0));
+            total.1 += 1;
+            total.2 += steps;
+            total.3 = max(total.3, steps);
+        }
+        println!("\nInterrupt\tCount\tSteps\tAverage\tLongest");
+        for (kind, count, steps, longest) in totals.values() {
+            println!("{}\t{}\t{}\t{:.1}\t{}", kind, count, steps, *steps as f64 / *count as f64, longest);
+        }
+        println!("all interrupts\t{}\t{}", totals.values().map(|t| t.1).sum::<usize>(),
+                 totals.values().map(|t| t.2).sum::<usize>());
+    }
+
+    /// print every write to a custom chip or CIA register with step index, pc and value
+    pub fn hw_log(&self, fmt: &FormatHelper) {
+        let filter = self.step_filter(fmt);
+        println!("Index\tPC\tRegister\tValue\tDecoded");
+        for (i, step) in self.steps.iter().enumerate() {
+            let (address, size, value) = match step.memory_write() {
+                Some(write) if hardware::is_hardware(write.0) && filter.shows(i) => write,
+                _ => continue
+            };
+            let name = hardware::access_name(address, size).unwrap_or_else(|| format!("{:08X}", address));
+            match value {
+                Some(value) => {
+                    let described = hardware::describe_write(address, size, value).unwrap_or_default();
+                    let decoded = described.strip_prefix(&format!("{}=", name)).unwrap_or(&described);
+                    println!("{}\t{}\t{}\t${:0width$X}\t{}", i, fmt.pc(step.pc), name, value, decoded,
+                             width = size.bytes() as usize * 2)
+                }
+                None => println!("{}\t{}\t{}\t?\t{}", i, fmt.pc(step.pc), name, step.instruction())
+            }
+        }
+    }
+
+    /// collect executed instructions with translated addresses. Instructions outside of the
+    /// segments and below the offset can not be translated and are skipped.
+    pub fn coverage(&self, fmt: &FormatHelper) -> Coverage {
+        let filter = self.step_filter(fmt);
+        let mut coverage = Coverage::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            let address = match fmt.segments.translate(step.pc) {
+                Some(address) if filter.shows(i) => address,
+                _ => continue
+            };
+            // the next pc is the address after the instruction; other sizes come from broken log
+            // lines or pcs wrapping around, and would not fit into a coverage block
+            let size = step.pc_next.wrapping_sub(step.pc);
+            if coverage::INSTRUCTION_SIZES.contains(&size) {
+                coverage.instructions.insert(address, size);
+            }
+        }
+        coverage
+    }
+
+    /// bytes of every executed instruction by pc
+    pub fn executed_code(&self) -> BTreeMap<u32, Vec<u8>> {
+        let mut code = BTreeMap::new();
+        for step in self.steps.iter() {
+            code.entry(step.pc).or_insert_with(|| step.bytes());
+        }
+        code
+    }
+
     /// print starting points found in dump
     pub fn starting_pcs(&self, offset: u32) {
         for pc in self.singles.keys() {
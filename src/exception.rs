@@ -0,0 +1,186 @@
+/*
+    Copyright (C) 2020 Arkadiusz Guzinski <kermit@ag.de1.cc>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::fmt;
+use crate::cpustep::CpuStep;
+use crate::memdump::MemDump;
+use crate::operand::Operand;
+
+/// Amiga interrupt sources per autovector level (1-7)
+const INTERRUPT_SOURCES: [&str; 7] = [
+    "TBE/DSKBLK/SOFT", "PORTS/CIA-A", "COPER/VERTB/BLIT", "AUD0-3", "RBF/DSKSYN", "EXTER/CIA-B", "NMI",
+];
+
+/// exception vectors, as read from memory at VBR
+pub struct VectorTable {
+    /// handler address per vector number, if in memory dump
+    pub handlers: Vec<Option<u32>>,
+}
+
+impl VectorTable {
+    /// read the 256 vectors at vbr from mem
+    pub fn from_mem(mem: &MemDump, vbr: u32) -> VectorTable {
+        VectorTable {
+            handlers: (0..256u32).map(|v| mem.long_at(vbr.wrapping_add(v * 4))).collect(),
+        }
+    }
+
+    /// lowest vector number with handler, if any
+    pub fn vector_for(&self, handler: u32) -> Option<u8> {
+        self.handlers.iter().position(|h| *h == Some(handler)).map(|v| v as u8)
+    }
+}
+
+/// an exception in a dump
+pub struct ExceptionEntry {
+    /// index of the step before the exception
+    pub index: usize,
+    pub kind: ExceptionKind,
+    /// first pc of the handler
+    pub handler: u32,
+    /// index and pc of the step the handler returned to
+    pub returned: Option<(usize, u32)>,
+}
+
+/// the kind of an exception, as far as it can be told from the dump
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ExceptionKind {
+    BusError,
+    AddressError,
+    Illegal,
+    ZeroDivide,
+    Chk,
+    Trapv,
+    Privilege,
+    Trace,
+    LineA,
+    LineF,
+    Format,
+    Spurious,
+    /// autovector interrupt with level 1-7
+    Interrupt(u8),
+    /// TRAP #n
+    Trap(u8),
+    /// any other vector
+    Vector(u8),
+    Unknown,
+}
+
+impl ExceptionKind {
+    /// kind of the exception using vector number vector
+    pub fn from_vector(vector: u8) -> ExceptionKind {
+        match vector {
+            2 => ExceptionKind::BusError,
+            3 => ExceptionKind::AddressError,
+            4 => ExceptionKind::Illegal,
+            5 => ExceptionKind::ZeroDivide,
+            6 => ExceptionKind::Chk,
+            7 => ExceptionKind::Trapv,
+            8 => ExceptionKind::Privilege,
+            9 => ExceptionKind::Trace,
+            10 => ExceptionKind::LineA,
+            11 => ExceptionKind::LineF,
+            14 => ExceptionKind::Format,
+            24 => ExceptionKind::Spurious,
+            25..=31 => ExceptionKind::Interrupt(vector - 24),
+            32..=47 => ExceptionKind::Trap(vector - 32),
+            _ => ExceptionKind::Vector(vector)
+        }
+    }
+
+    /// vector number used by this kind of exception
+    pub fn vector(&self) -> Option<u8> {
+        match self {
+            ExceptionKind::BusError => Some(2),
+            ExceptionKind::AddressError => Some(3),
+            ExceptionKind::Illegal => Some(4),
+            ExceptionKind::ZeroDivide => Some(5),
+            ExceptionKind::Chk => Some(6),
+            ExceptionKind::Trapv => Some(7),
+            ExceptionKind::Privilege => Some(8),
+            ExceptionKind::Trace => Some(9),
+            ExceptionKind::LineA => Some(10),
+            ExceptionKind::LineF => Some(11),
+            ExceptionKind::Format => Some(14),
+            ExceptionKind::Spurious => Some(24),
+            ExceptionKind::Interrupt(level) => Some(24 + level),
+            ExceptionKind::Trap(n) => Some(32 + n),
+            ExceptionKind::Vector(v) => Some(*v),
+            ExceptionKind::Unknown => None
+        }
+    }
+
+    /// classify the exception entered by the step to next. Interrupts are recognized by the raised
+    /// mask, instructions causing exceptions directly, anything else by looking up the handler in
+    /// the vector table.
+    pub fn classify(step: &CpuStep, next: &CpuStep, vectors: Option<&VectorTable>) -> ExceptionKind {
+        if next.imask > step.imask {
+            return ExceptionKind::Interrupt(next.imask);
+        }
+        // fs-uae disassembles unknown opcodes as ILLEGAL, so check for line A/F first
+        match step.opcode().map(|o| o >> 12) {
+            Some(0xA) => return ExceptionKind::LineA,
+            Some(0xF) => return ExceptionKind::LineF,
+            _ => {}
+        }
+        match step.mnemonic() {
+            "TRAP" => {
+                if let Some(Operand::Immediate(n)) = step.operands().first() {
+                    return ExceptionKind::Trap((*n & 0xF) as u8);
+                }
+            }
+            "ILLEGAL" => return ExceptionKind::Illegal,
+            "DIVU" | "DIVS" => return ExceptionKind::ZeroDivide,
+            "CHK" | "CHK2" => return ExceptionKind::Chk,
+            "TRAPV" => return ExceptionKind::Trapv,
+            _ => {}
+        }
+        match vectors.and_then(|v| v.vector_for(next.pc)) {
+            Some(vector) => ExceptionKind::from_vector(vector),
+            None => ExceptionKind::Unknown
+        }
+    }
+}
+
+impl fmt::Display for ExceptionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExceptionKind::BusError => write!(f, "Bus error"),
+            ExceptionKind::AddressError => write!(f, "Address error"),
+            ExceptionKind::Illegal => write!(f, "Illegal instruction"),
+            ExceptionKind::ZeroDivide => write!(f, "Division by zero"),
+            ExceptionKind::Chk => write!(f, "CHK"),
+            ExceptionKind::Trapv => write!(f, "TRAPV"),
+            ExceptionKind::Privilege => write!(f, "Privilege violation"),
+            ExceptionKind::Trace => write!(f, "Trace"),
+            ExceptionKind::LineA => write!(f, "Line-A"),
+            ExceptionKind::LineF => write!(f, "Line-F"),
+            ExceptionKind::Format => write!(f, "Format error"),
+            ExceptionKind::Spurious => write!(f, "Spurious interrupt"),
+            ExceptionKind::Interrupt(level) => write!(f, "Interrupt level {} ({})", level,
+                                                      INTERRUPT_SOURCES[(*level as usize).clamp(1, 7) - 1]),
+            ExceptionKind::Trap(n) => write!(f, "TRAP #{}", n),
+            ExceptionKind::Vector(v) => write!(f, "Vector {} (${:03X})", v, *v as u32 * 4),
+            ExceptionKind::Unknown => write!(f, "Exception"),
+        }
+    }
+}
+
+/// describe the exception entered by the step to next, reading vectors from mem if needed
+pub fn describe(step: &CpuStep, next: &CpuStep, mem: &MemDump) -> ExceptionKind {
+    let vectors = VectorTable::from_mem(mem, next.vbr);
+    ExceptionKind::classify(step, next, Some(&vectors))
+}
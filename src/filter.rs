@@ -0,0 +1,99 @@
+/*
+    Copyright (C) 2020 Arkadiusz Guzinski <kermit@ag.de1.cc>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use crate::context::{Context, InterruptMode};
+use crate::ranges::{Region, RegionMode};
+use crate::utils::FormatHelper;
+
+/// a run of consecutive hidden steps, that is printed as one line
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FoldGroup {
+    /// steps of an interrupt handler
+    Context(Context),
+    /// an excursion into ROM or OS code
+    Region(Region),
+}
+
+/// decides which steps of a dump are analysed, by their execution context and the region of
+/// their pc
+pub struct StepFilter {
+    interrupts: InterruptMode,
+    regions: RegionMode,
+    /// context per step, empty if not needed by interrupts
+    contexts: Vec<Context>,
+    /// region of the pc per step, empty if not needed by regions
+    pcs: Vec<Region>,
+}
+
+impl StepFilter {
+    pub fn new(interrupts: InterruptMode, contexts: Vec<Context>, regions: RegionMode, pcs: Vec<Region>) -> StepFilter {
+        StepFilter { interrupts, regions, contexts, pcs }
+    }
+
+    /// context of step idx. Only known if contexts were determined for the filter.
+    pub fn context(&self, idx: usize) -> Context {
+        self.contexts.get(idx).copied().unwrap_or(Context::Main)
+    }
+
+    /// region of step idx. Only known if regions were determined for the filter.
+    pub fn region(&self, idx: usize) -> Region {
+        self.pcs.get(idx).copied().unwrap_or(Region::Program)
+    }
+
+    fn shows_context(&self, idx: usize) -> bool {
+        match self.interrupts {
+            InterruptMode::Include => true,
+            InterruptMode::Exclude | InterruptMode::Fold => !self.context(idx).is_interrupt(),
+            InterruptMode::Only => self.context(idx).is_interrupt(),
+        }
+    }
+
+    /// should step idx be analysed?
+    pub fn shows(&self, idx: usize) -> bool {
+        self.shows_context(idx) && (self.regions == RegionMode::Include || !self.region(idx).is_system())
+    }
+
+    /// group of step idx, if it is hidden, but to be mentioned as part of a folded run of steps.
+    /// Interrupt contexts take precedence over regions.
+    pub fn fold_group(&self, idx: usize) -> Option<FoldGroup> {
+        if !self.shows_context(idx) {
+            return match self.interrupts {
+                InterruptMode::Fold => Some(FoldGroup::Context(self.context(idx))),
+                _ => None
+            };
+        }
+        match self.regions {
+            RegionMode::Collapse if self.region(idx).is_system() => Some(FoldGroup::Region(self.region(idx))),
+            _ => None
+        }
+    }
+
+    /// number of consecutive steps starting with idx, that are hidden in the same way
+    pub fn hidden_run(&self, idx: usize, end: usize) -> usize {
+        let group = self.fold_group(idx);
+        (idx..end).take_while(|k| !self.shows(*k) && self.fold_group(*k) == group).count()
+    }
+
+    /// line describing a folded run of len steps in group, the first one at pc
+    pub fn describe_fold(&self, group: FoldGroup, pc: u32, len: usize, fmt: &FormatHelper) -> String {
+        match (group, fmt.name_for(pc)) {
+            (FoldGroup::Context(context), _) => format!("[{}: {} steps]", context, len),
+            (FoldGroup::Region(region), Some(name)) =>
+                format!("\u{2192} {} {:08X} {} ({} steps)", region, pc, name, len),
+            (FoldGroup::Region(region), None) => format!("\u{2192} {} {:08X} ({} steps)", region, pc, len),
+        }
+    }
+}
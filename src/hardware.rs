@@ -0,0 +1,228 @@
+/*
+    Copyright (C) 2020 Arkadiusz Guzinski <kermit@ag.de1.cc>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use crate::operand::Size;
+
+/// base address of the custom chip registers
+pub const CUSTOM_BASE: u32 = 0x00DFF000;
+/// base address of CIA-A (registers at odd addresses)
+pub const CIAA_BASE: u32 = 0x00BFE001;
+/// base address of CIA-B (registers at even addresses)
+pub const CIAB_BASE: u32 = 0x00BFD000;
+
+/// custom chip registers, that are not part of a numbered group
+const CUSTOM_REGISTERS: [(u16, &str); 108] = [
+    (0x000, "BLTDDAT"), (0x002, "DMACONR"), (0x004, "VPOSR"), (0x006, "VHPOSR"),
+    (0x008, "DSKDATR"), (0x00A, "JOY0DAT"), (0x00C, "JOY1DAT"), (0x00E, "CLXDAT"),
+    (0x010, "ADKCONR"), (0x012, "POT0DAT"), (0x014, "POT1DAT"), (0x016, "POTGOR"),
+    (0x018, "SERDATR"), (0x01A, "DSKBYTR"), (0x01C, "INTENAR"), (0x01E, "INTREQR"),
+    (0x020, "DSKPTH"), (0x022, "DSKPTL"), (0x024, "DSKLEN"), (0x026, "DSKDAT"),
+    (0x028, "REFPTR"), (0x02A, "VPOSW"), (0x02C, "VHPOSW"), (0x02E, "COPCON"),
+    (0x030, "SERDAT"), (0x032, "SERPER"), (0x034, "POTGO"), (0x036, "JOYTEST"),
+    (0x038, "STREQU"), (0x03A, "STRVBL"), (0x03C, "STRHOR"), (0x03E, "STRLONG"),
+    (0x040, "BLTCON0"), (0x042, "BLTCON1"), (0x044, "BLTAFWM"), (0x046, "BLTALWM"),
+    (0x048, "BLTCPTH"), (0x04A, "BLTCPTL"), (0x04C, "BLTBPTH"), (0x04E, "BLTBPTL"),
+    (0x050, "BLTAPTH"), (0x052, "BLTAPTL"), (0x054, "BLTDPTH"), (0x056, "BLTDPTL"),
+    (0x058, "BLTSIZE"), (0x05A, "BLTCON0L"), (0x05C, "BLTSIZV"), (0x05E, "BLTSIZH"),
+    (0x060, "BLTCMOD"), (0x062, "BLTBMOD"), (0x064, "BLTAMOD"), (0x066, "BLTDMOD"),
+    (0x070, "BLTCDAT"), (0x072, "BLTBDAT"), (0x074, "BLTADAT"), (0x078, "SPRHDAT"),
+    (0x07A, "BPLHDAT"), (0x07C, "DENISEID"), (0x07E, "DSKSYNC"),
+    (0x080, "COP1LCH"), (0x082, "COP1LCL"), (0x084, "COP2LCH"), (0x086, "COP2LCL"),
+    (0x088, "COPJMP1"), (0x08A, "COPJMP2"), (0x08C, "COPINS"), (0x08E, "DIWSTRT"),
+    (0x090, "DIWSTOP"), (0x092, "DDFSTRT"), (0x094, "DDFSTOP"), (0x096, "DMACON"),
+    (0x098, "CLXCON"), (0x09A, "INTENA"), (0x09C, "INTREQ"), (0x09E, "ADKCON"),
+    (0x100, "BPLCON0"), (0x102, "BPLCON1"), (0x104, "BPLCON2"), (0x106, "BPLCON3"),
+    (0x108, "BPL1MOD"), (0x10A, "BPL2MOD"), (0x10C, "BPLCON4"), (0x10E, "CLXCON2"),
+    (0x1C0, "HTOTAL"), (0x1C2, "HSSTOP"), (0x1C4, "HBSTRT"), (0x1C6, "HBSTOP"),
+    (0x1C8, "VTOTAL"), (0x1CA, "VSSTOP"), (0x1CC, "VBSTRT"), (0x1CE, "VBSTOP"),
+    (0x1D0, "SPRHSTRT"), (0x1D2, "SPRHSTOP"), (0x1D4, "BPLHSTRT"), (0x1D6, "BPLHSTOP"),
+    (0x1D8, "HHPOSW"), (0x1DA, "HHPOSR"), (0x1DC, "BEAMCON0"), (0x1DE, "HSSTRT"),
+    (0x1E0, "VSSTRT"), (0x1E2, "HCENTER"), (0x1E4, "DIWHIGH"), (0x1E6, "BPLHMOD"),
+    (0x1E8, "SPRHPTH"), (0x1EA, "SPRHPTL"), (0x1EC, "BPLHPTH"), (0x1EE, "BPLHPTL"),
+    (0x1FC, "FMODE"),
+];
+
+/// CIA registers, by register number
+const CIA_REGISTERS: [&str; 16] = [
+    "PRA", "PRB", "DDRA", "DDRB", "TALO", "TAHI", "TBLO", "TBHI",
+    "TODLO", "TODMID", "TODHI", "UNUSED", "SDR", "ICR", "CRA", "CRB",
+];
+
+/// bits of DMACON(R), from bit 15 down
+const DMACON_BITS: [&str; 16] = [
+    "", "BBUSY", "BZERO", "", "", "BLTPRI", "DMAEN", "BPLEN",
+    "COPEN", "BLTEN", "SPREN", "DSKEN", "AUD3EN", "AUD2EN", "AUD1EN", "AUD0EN",
+];
+
+/// bits of INTENA(R) and INTREQ(R), from bit 15 down
+const INT_BITS: [&str; 16] = [
+    "", "INTEN", "EXTER", "DSKSYN", "RBF", "AUD3", "AUD2", "AUD1",
+    "AUD0", "BLIT", "VERTB", "COPER", "PORTS", "SOFT", "DSKBLK", "TBE",
+];
+
+/// bits of ADKCON(R), from bit 15 down
+const ADKCON_BITS: [&str; 16] = [
+    "", "PRECOMP1", "PRECOMP0", "MFMPREC", "UARTBRK", "WORDSYNC", "MSBSYNC", "FAST",
+    "USE3PN", "USE2P3", "USE1P2", "USE0P1", "USE3VN", "USE2V3", "USE1V2", "USE0V1",
+];
+
+/// bits of BPLCON0, from bit 15 down. Bits 14-12 (BPU) are decoded separately.
+const BPLCON0_BITS: [&str; 16] = [
+    "HIRES", "", "", "", "HAM", "DPF", "COLOR", "GAUD",
+    "UHRES", "SHRES", "BYPASS", "BPU3", "LPEN", "LACE", "ERSY", "ECSENA",
+];
+
+/// bits of BLTCON1, from bit 15 down. Bits 15-12 (BSH) are decoded separately.
+const BLTCON1_BITS: [&str; 16] = [
+    "", "", "", "", "", "", "", "",
+    "DOFF", "", "", "EFE", "IFE", "FCI", "DESC", "LINE",
+];
+
+/// bits of CIA ICR, from bit 7 down
+const CIA_ICR_BITS: [&str; 8] = ["", "", "", "FLG", "SP", "ALRM", "TB", "TA"];
+
+/// bits of CIA CRA, from bit 7 down
+const CIA_CRA_BITS: [&str; 8] = ["TODIN", "SPMODE", "INMODE", "LOAD", "RUNMODE", "OUTMODE", "PBON", "START"];
+
+/// bits of CIA CRB, from bit 7 down
+const CIA_CRB_BITS: [&str; 8] = ["ALARM", "INMODE1", "INMODE0", "LOAD", "RUNMODE", "OUTMODE", "PBON", "START"];
+
+/// is address in the custom chip or CIA register range?
+pub fn is_hardware(address: u32) -> bool {
+    (CUSTOM_BASE..CUSTOM_BASE + 0x200).contains(&address) || (CIAB_BASE..=CIAA_BASE + 0xF00).contains(&address)
+}
+
+/// name of the custom chip register at offset (from $DFF000)
+fn custom_register(offset: u16) -> Option<String> {
+    let offset = offset & 0x1FE;
+    if let Some((_, name)) = CUSTOM_REGISTERS.iter().find(|(o, _)| *o == offset) {
+        return Some(name.to_string());
+    }
+    let hl = if offset & 2 == 0 { "H" } else { "L" };
+    match offset {
+        0x0A0..=0x0DF => {
+            let channel = (offset - 0x0A0) / 0x10;
+            let reg = ["LCH", "LCL", "LEN", "PER", "VOL", "DAT", "", ""][((offset & 0xF) / 2) as usize];
+            if reg.is_empty() { None } else { Some(format!("AUD{}{}", channel, reg)) }
+        }
+        0x0E0..=0x0FF => Some(format!("BPL{}PT{}", (offset - 0x0E0) / 4 + 1, hl)),
+        0x110..=0x11F => Some(format!("BPL{}DAT", (offset - 0x110) / 2 + 1)),
+        0x120..=0x13F => Some(format!("SPR{}PT{}", (offset - 0x120) / 4, hl)),
+        0x140..=0x17F => Some(format!("SPR{}{}", (offset - 0x140) / 8,
+                                      ["POS", "CTL", "DATA", "DATB"][((offset & 7) / 2) as usize])),
+        0x180..=0x1BF => Some(format!("COLOR{:02}", (offset - 0x180) / 2)),
+        0x1FE => Some("NO-OP".to_string()),
+        _ => None
+    }
+}
+
+/// name of the register at address, e.g. "DMACON" or "CIAA PRA"
+pub fn register_name(address: u32) -> Option<String> {
+    if (CUSTOM_BASE..CUSTOM_BASE + 0x200).contains(&address) {
+        return custom_register((address - CUSTOM_BASE) as u16);
+    }
+    let reg = ((address >> 8) & 0xF) as usize;
+    match address & !0xF00 {
+        CIAA_BASE => Some(format!("CIAA {}", CIA_REGISTERS[reg])),
+        CIAB_BASE => Some(format!("CIAB {}", CIA_REGISTERS[reg])),
+        _ => None
+    }
+}
+
+/// name for an access of size at address. Long accesses to the high word of a pointer are named
+/// after the pointer (e.g. "BPL1PT"), others after both registers (e.g. "INTENA/INTREQ").
+pub fn access_name(address: u32, size: Size) -> Option<String> {
+    let name = register_name(address)?;
+    if size != Size::Long || !(CUSTOM_BASE..CUSTOM_BASE + 0x200).contains(&address) {
+        return Some(name);
+    }
+    let second = register_name(address + 2).unwrap_or_default();
+    match name.strip_suffix('H') {
+        Some(pointer) if second.strip_suffix('L') == Some(pointer) => Some(pointer.to_string()),
+        _ => Some(format!("{}/{}", name, second))
+    }
+}
+
+/// names of the set bits, with bit_names listing names from the highest bit down
+fn bits(value: u32, bit_names: &[&str]) -> Vec<String> {
+    let top = bit_names.len() - 1;
+    bit_names.iter().enumerate()
+        .filter(|(i, name)| !name.is_empty() && value & (1 << (top - i)) != 0)
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+/// decode a value written to a set/clear register (bit 15 selects, if the other bits are set or
+/// cleared)
+fn set_clear(value: u32, set_bit: u32, bit_names: &[&str]) -> String {
+    let mut parts = vec![if value & set_bit != 0 { "SET".to_string() } else { "CLR".to_string() }];
+    parts.extend(bits(value, bit_names));
+    parts.join("|")
+}
+
+/// decode a word (or byte for CIAs) written to the register at address into its bitfields
+pub fn decode(address: u32, value: u32) -> String {
+    let name = register_name(address).unwrap_or_default();
+    match name.as_str() {
+        "DMACON" => set_clear(value, 0x8000, &DMACON_BITS),
+        "INTENA" | "INTREQ" => set_clear(value, 0x8000, &INT_BITS),
+        "ADKCON" => set_clear(value, 0x8000, &ADKCON_BITS),
+        "DMACONR" => bits(value, &DMACON_BITS).join("|"),
+        "INTENAR" | "INTREQR" => bits(value, &INT_BITS).join("|"),
+        "ADKCONR" => bits(value, &ADKCON_BITS).join("|"),
+        "BPLCON0" => {
+            let planes = ((value >> 12) & 7) | if value & 0x10 != 0 { 8 } else { 0 };
+            let mut parts = vec![format!("BPU={}", planes)];
+            parts.extend(bits(value, &BPLCON0_BITS).into_iter().filter(|b| b != "BPU3"));
+            parts.join(" ")
+        }
+        "BLTCON0" => {
+            let mut parts = vec![format!("ASH={}", (value >> 12) & 0xF)];
+            parts.extend(bits(value >> 8, &["USEA", "USEB", "USEC", "USED"]));
+            parts.push(format!("LF=${:02X}", value & 0xFF));
+            parts.join(" ")
+        }
+        "BLTCON1" => {
+            let mut parts = vec![format!("BSH={}", (value >> 12) & 0xF)];
+            parts.extend(bits(value, &BLTCON1_BITS));
+            parts.join(" ")
+        }
+        "BLTSIZE" => {
+            let height = (value >> 6) & 0x3FF;
+            let width = value & 0x3F;
+            format!("H={} W={}", if height == 0 { 1024 } else { height }, if width == 0 { 64 } else { width })
+        }
+        "CIAA ICR" | "CIAB ICR" => set_clear(value, 0x80, &CIA_ICR_BITS),
+        "CIAA CRA" | "CIAB CRA" => bits(value, &CIA_CRA_BITS).join("|"),
+        "CIAA CRB" | "CIAB CRB" => bits(value, &CIA_CRB_BITS).join("|"),
+        n if n.starts_with("COLOR") => format!("R={:X} G={:X} B={:X}", (value >> 8) & 0xF, (value >> 4) & 0xF, value & 0xF),
+        n if n.starts_with("CIA") => format!("${:02X}", value),
+        _ => format!("${:04X}", value)
+    }
+}
+
+/// describe a write of value with size to address, e.g. "DMACON=SET|DMAEN|SPREN"
+pub fn describe_write(address: u32, size: Size, value: u32) -> Option<String> {
+    let name = access_name(address, size)?;
+    if size == Size::Long {
+        if name.contains('/') {
+            return Some(format!("{}={}  {}={}", register_name(address)?, decode(address, value >> 16),
+                                register_name(address + 2).unwrap_or_default(), decode(address + 2, value & 0xFFFF)));
+        }
+        return Some(format!("{}=${:08X}", name, value));
+    }
+    Some(format!("{}={}", name, decode(address, value)))
+}
@@ -0,0 +1,567 @@
+/*
+    Copyright (C) 2020 Arkadiusz Guzinski <kermit@ag.de1.cc>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::memdump::MemDump;
+
+const HUNK_NAME: u32 = 0x3E8;
+const HUNK_CODE: u32 = 0x3E9;
+const HUNK_DATA: u32 = 0x3EA;
+const HUNK_BSS: u32 = 0x3EB;
+const HUNK_RELOC32: u32 = 0x3EC;
+const HUNK_SYMBOL: u32 = 0x3F0;
+const HUNK_DEBUG: u32 = 0x3F1;
+const HUNK_END: u32 = 0x3F2;
+const HUNK_HEADER: u32 = 0x3F3;
+const HUNK_OVERLAY: u32 = 0x3F5;
+const HUNK_BREAK: u32 = 0x3F6;
+const HUNK_RELOC32SHORT: u32 = 0x3FC;
+/// HUNK_RELOC32SHORT as written by some old linkers
+const HUNK_DREL32: u32 = 0x3F7;
+
+/// number of candidates for the load address of a hunk that are compared in full
+const MAX_CANDIDATES: usize = 32;
+/// length of the part of a hunk that is searched in memory
+const MAX_PATTERN: u32 = 64;
+/// hunks without a part of this length without relocations are not searched in memory
+const MIN_PATTERN: u32 = 16;
+
+/// type of a hunk
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HunkKind { Code, Data, Bss }
+
+impl fmt::Display for HunkKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HunkKind::Code => write!(f, "CODE"),
+            HunkKind::Data => write!(f, "DATA"),
+            HunkKind::Bss => write!(f, "BSS"),
+        }
+    }
+}
+
+/// a hunk of an executable, as it is loaded to memory
+pub struct Hunk {
+    pub kind: HunkKind,
+    /// size in memory in bytes (at least the size of data)
+    pub size: u32,
+    /// content from the file, without the zero padding up to size
+    pub data: Vec<u8>,
+    /// offsets of longs in data that are relocated when loading -> index of the hunk they point to
+    pub relocs: BTreeMap<u32, usize>,
+    /// name from HUNK_NAME
+    pub name: Option<String>,
+    /// offset -> name, from HUNK_SYMBOL
+    pub symbols: BTreeMap<u32, String>,
+    /// position of the content in the file
+    pub file_offset: u32,
+    /// memory requirements from the header (MEMF_CHIP, MEMF_FAST in the upper bits)
+    pub mem_flags: u32,
+    /// overlay node the hunk belongs to, None for the root node
+    pub overlay: Option<usize>,
+}
+
+/// where a hunk was found in a dump
+pub struct HunkMatch {
+    /// address the hunk was loaded to
+    pub address: u32,
+    /// number of matching and differing executed instructions or bytes in memory
+    pub matching: usize,
+    pub differing: usize,
+    /// number of load addresses that were equally good
+    pub candidates: usize,
+}
+
+impl Hunk {
+    /// memory type required by the hunk
+    pub fn memory(&self) -> &str {
+        match self.mem_flags {
+            0x40000000 => "chip",
+            0x80000000 => "fast",
+            0 => "any",
+            _ => "other"
+        }
+    }
+
+    /// is the byte at offset part of a relocated long, i.e. differs between file and memory?
+    pub fn is_relocated(&self, offset: u32) -> bool {
+        self.relocs.range(offset.saturating_sub(3)..=offset).next().is_some()
+    }
+
+    /// do bytes match the content at offset? Relocated bytes match anything.
+    pub fn matches_at(&self, offset: u32, bytes: &[u8]) -> bool {
+        match self.data.get(offset as usize..offset as usize + bytes.len()) {
+            Some(content) => content.iter().zip(bytes).enumerate()
+                .all(|(i, (c, b))| c == b || self.is_relocated(offset + i as u32)),
+            None => false
+        }
+    }
+
+    /// find the load address by matching executed instructions (pc -> bytes) against the content.
+    /// Every executed instruction of at least 4 bytes votes for the load addresses at which it
+    /// would be part of the hunk, found by its first 4 bytes (without relocations). The candidates
+    /// with the most votes are compared in full, the best one is the one most executed
+    /// instructions within the hunk match.
+    pub fn locate_in_steps(&self, executed: &BTreeMap<u32, Vec<u8>>) -> Option<HunkMatch> {
+        let mut index: HashMap<&[u8], Vec<u32>> = HashMap::new();
+        for offset in (0..self.data.len().saturating_sub(3) as u32).step_by(2) {
+            if !(offset..offset + 4).any(|o| self.is_relocated(o)) {
+                index.entry(&self.data[offset as usize..offset as usize + 4]).or_default().push(offset);
+            }
+        }
+        let mut votes: BTreeMap<u32, usize> = BTreeMap::new();
+        for (pc, bytes) in executed.iter().filter(|(_, b)| b.len() >= 4) {
+            for offset in index.get(&bytes[..4]).into_iter().flatten().filter(|o| *pc >= **o) {
+                *votes.entry(pc - offset).or_default() += 1;
+            }
+        }
+        let mut candidates: Vec<(u32, usize)> = votes.into_iter().collect();
+        candidates.sort_by_key(|(address, count)| (std::cmp::Reverse(*count), *address));
+        let mut best: Option<HunkMatch> = None;
+        for (address, _) in candidates.into_iter().take(MAX_CANDIDATES) {
+            let (mut matching, mut differing) = (0, 0);
+            for (pc, bytes) in executed.range(address..address.saturating_add(self.data.len() as u32)) {
+                if self.matches_at(pc - address, bytes) { matching += 1 } else { differing += 1 }
+            }
+            best = HunkMatch::better(best, HunkMatch { address, matching, differing, candidates: 1 });
+        }
+        best
+    }
+
+    /// find the load address by searching mem for the longest part of the content without
+    /// relocations, and comparing the whole content at each position found
+    pub fn locate_in_mem(&self, mem: &MemDump) -> Option<HunkMatch> {
+        // (start, length) of the longest run of bytes without relocations
+        let mut longest = (0, 0);
+        let mut start = 0;
+        for offset in 0..=self.data.len() as u32 {
+            if offset == self.data.len() as u32 || self.is_relocated(offset) {
+                if offset - start > longest.1 {
+                    longest = (start, offset - start);
+                }
+                start = offset + 1;
+            }
+        }
+        let length = longest.1.min(MAX_PATTERN);
+        if length < MIN_PATTERN {
+            return None;
+        }
+        let pattern = &self.data[longest.0 as usize..(longest.0 + length) as usize];
+        let mut best: Option<HunkMatch> = None;
+        for found in mem.find(pattern) {
+            best = HunkMatch::better(best, self.compare_with_mem(found.wrapping_sub(longest.0), mem));
+        }
+        best
+    }
+
+    /// compare the content with mem at address, byte by byte. Bytes missing in mem differ.
+    pub fn compare_with_mem(&self, address: u32, mem: &MemDump) -> HunkMatch {
+        let (mut matching, mut differing) = (0, 0);
+        for (i, byte) in self.data.iter().enumerate() {
+            match mem.bytes_at(address.wrapping_add(i as u32), 1) {
+                Some(b) if b[0] == *byte || self.is_relocated(i as u32) => matching += 1,
+                _ => differing += 1
+            }
+        }
+        HunkMatch { address, matching, differing, candidates: 1 }
+    }
+
+    /// load addresses of the hunks the relocations point to, given that this hunk was loaded to
+    /// address: target hunk -> (address, number of relocations agreeing on it). A relocated long
+    /// holds an offset into the target hunk in the file and the address in memory, so the target
+    /// was loaded to their difference. If relocations disagree, the majority wins.
+    pub fn reloc_targets(&self, address: u32, mem: &MemDump) -> BTreeMap<usize, (u32, usize)> {
+        let mut votes: BTreeMap<(usize, u32), usize> = BTreeMap::new();
+        for (offset, target) in &self.relocs {
+            let file = match self.data.get(*offset as usize..*offset as usize + 4) {
+                Some(b) => u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+                None => continue
+            };
+            if let Some(loaded) = mem.long_at(address.wrapping_add(*offset)) {
+                *votes.entry((*target, loaded.wrapping_sub(file))).or_default() += 1;
+            }
+        }
+        let mut targets: BTreeMap<usize, (u32, usize)> = BTreeMap::new();
+        for ((target, start), count) in votes {
+            if targets.get(&target).is_none_or(|(_, best)| count > *best) {
+                targets.insert(target, (start, count));
+            }
+        }
+        targets
+    }
+}
+
+impl HunkMatch {
+    /// the better of two matches, counting equally good ones. Matches with no more matching than
+    /// differing instructions or bytes are no match at all.
+    fn better(best: Option<HunkMatch>, other: HunkMatch) -> Option<HunkMatch> {
+        let score = |m: &HunkMatch| m.matching as i64 - m.differing as i64;
+        if score(&other) <= 0 {
+            return best;
+        }
+        match best {
+            Some(mut best) if score(&best) == score(&other) => {
+                best.candidates += 1;
+                Some(best)
+            }
+            Some(best) if score(&best) > score(&other) => Some(best),
+            _ => Some(other)
+        }
+    }
+
+    /// share of matching instructions or bytes in percent
+    pub fn confidence(&self) -> f64 {
+        100.0 * self.matching as f64 / (self.matching + self.differing).max(1) as f64
+    }
+}
+
+/// an AmigaOS executable in hunk format
+pub struct HunkFile {
+    pub hunks: Vec<Hunk>,
+    /// number of overlay nodes (0 for executables without overlays)
+    pub overlays: usize,
+}
+
+impl HunkFile {
+    /// load executable from path
+    pub fn from_file(path: &Path) -> io::Result<HunkFile> {
+        HunkFile::parse(&fs::read(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// parse the content of an executable. Hunks of overlay nodes follow those of the root node.
+    pub fn parse(data: &[u8]) -> Result<HunkFile, String> {
+        let mut reader = Reader { data, pos: 0 };
+        if reader.long()? != HUNK_HEADER {
+            return Err("not an AmigaOS executable (no HUNK_HEADER)".to_string());
+        }
+        let mut sizes = reader.header()?;
+        let mut hunks: Vec<Hunk> = Vec::new();
+        // name for the next hunk (HUNK_NAME precedes the content)
+        let mut name = None;
+        // current overlay node
+        let mut overlay = None;
+        while reader.pos + 4 <= data.len() {
+            let block = reader.long()? & 0x3FFFFFFF;
+            match block {
+                HUNK_CODE | HUNK_DATA | HUNK_BSS => {
+                    let longs = reader.long()? & 0x3FFFFFFF;
+                    let file_offset = reader.pos as u32;
+                    let (kind, content) = match block {
+                        HUNK_CODE => (HunkKind::Code, reader.longs(longs)?.to_vec()),
+                        HUNK_DATA => (HunkKind::Data, reader.longs(longs)?.to_vec()),
+                        _ => (HunkKind::Bss, Vec::new())
+                    };
+                    let (size, flags) = sizes.get(hunks.len()).copied().unwrap_or((0, 0));
+                    hunks.push(Hunk {
+                        kind,
+                        size: size.max(longs * 4),
+                        data: content,
+                        relocs: BTreeMap::new(),
+                        name: name.take(),
+                        symbols: BTreeMap::new(),
+                        file_offset,
+                        mem_flags: flags,
+                        overlay,
+                    });
+                }
+                HUNK_RELOC32 => {
+                    let hunk = hunks.last_mut().ok_or("relocations before first hunk")?;
+                    loop {
+                        let count = reader.long()?;
+                        if count == 0 {
+                            break;
+                        }
+                        let target = reader.long()? as usize;
+                        for _ in 0..count {
+                            hunk.relocs.insert(reader.long()?, target);
+                        }
+                    }
+                }
+                HUNK_RELOC32SHORT | HUNK_DREL32 => {
+                    let hunk = hunks.last_mut().ok_or("relocations before first hunk")?;
+                    loop {
+                        let count = reader.word()? as u32;
+                        if count == 0 {
+                            break;
+                        }
+                        let target = reader.word()? as usize;
+                        for _ in 0..count {
+                            hunk.relocs.insert(reader.word()? as u32, target);
+                        }
+                    }
+                    // padded to longs
+                    if !reader.pos.is_multiple_of(4) {
+                        reader.word()?;
+                    }
+                }
+                HUNK_SYMBOL => {
+                    let hunk = hunks.last_mut().ok_or("symbols before first hunk")?;
+                    loop {
+                        let longs = reader.long()? & 0xFFFFFF;
+                        if longs == 0 {
+                            break;
+                        }
+                        let symbol = reader.string(longs)?;
+                        hunk.symbols.insert(reader.long()?, symbol);
+                    }
+                }
+                HUNK_NAME => {
+                    let longs = reader.long()?;
+                    name = Some(reader.string(longs)?);
+                }
+                HUNK_DEBUG => {
+                    let longs = reader.long()?;
+                    reader.longs(longs)?;
+                }
+                HUNK_END => {}
+                HUNK_OVERLAY => {
+                    // overlay table, used by the overlay manager of the root node
+                    let longs = reader.long()?;
+                    let longs = longs.checked_add(1).ok_or_else(|| reader.end_of_file())?;
+                    reader.longs(longs)?;
+                    overlay = Some(0);
+                }
+                // HUNK_BREAK ends an overlay node, some linkers start each one with a header
+                HUNK_BREAK | HUNK_HEADER if overlay.is_some() => {
+                    if hunks.last().is_some_and(|h| h.overlay == overlay) {
+                        overlay = overlay.map(|node| node + 1);
+                    }
+                    if block == HUNK_HEADER {
+                        sizes.truncate(hunks.len());
+                        sizes.extend(reader.header()?);
+                    }
+                }
+                other => return Err(format!("unsupported block {:X} at {:X}", other, reader.pos - 4))
+            }
+        }
+        if hunks.len() < sizes.len() {
+            return Err(format!("{} hunks declared, but only {} found", sizes.len(), hunks.len()));
+        }
+        let overlays = hunks.iter().filter_map(|h| h.overlay).max().map_or(0, |node| node + 1);
+        Ok(HunkFile { hunks, overlays })
+    }
+
+    /// address of each hunk, when loaded one after another starting at base (as done by Ghidra)
+    pub fn addresses(&self, base: u32) -> Vec<u32> {
+        let mut address = base;
+        self.hunks.iter().map(|h| {
+            let start = address;
+            address += h.size;
+            start
+        }).collect()
+    }
+
+    /// hunk containing address and offset inside it, given the address of each hunk
+    pub fn hunk_at(&self, starts: &[u32], address: u32) -> Option<(usize, u32)> {
+        self.hunks.iter().zip(starts).enumerate()
+            .find(|(_, (h, start))| address >= **start && address - **start < h.size)
+            .map(|(i, (_, start))| (i, address - start))
+    }
+
+    /// is the long at address relocated, given the address of each hunk?
+    pub fn is_relocated_at(&self, starts: &[u32], address: u32) -> bool {
+        self.hunk_at(starts, address).is_some_and(|(i, offset)| self.hunks[i].relocs.contains_key(&offset))
+    }
+
+    /// hunk and offset of each position of pattern in the content of the hunks. Masked bytes
+    /// (None) match anything.
+    pub fn find_pattern(&self, pattern: &[Option<u8>]) -> Vec<(usize, u32)> {
+        let mut found = Vec::new();
+        for (i, hunk) in self.hunks.iter().enumerate() {
+            for offset in (0..(hunk.data.len() + 1).saturating_sub(pattern.len())).step_by(2) {
+                if pattern.iter().zip(&hunk.data[offset..]).all(|(p, b)| p.is_none_or(|p| p == *b)) {
+                    found.push((i, offset as u32));
+                }
+            }
+        }
+        found
+    }
+
+    /// hunk and offset inside it for a position in the file, if it is inside the content of a hunk
+    pub fn hunk_at_file_offset(&self, position: u32) -> Option<(usize, u32)> {
+        self.hunks.iter().enumerate()
+            .find(|(_, h)| position >= h.file_offset && position - h.file_offset < h.data.len() as u32)
+            .map(|(i, h)| (i, position - h.file_offset))
+    }
+}
+
+/// reads big endian values from the content of a file
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn end_of_file(&self) -> String {
+        format!("unexpected end of file at {:X}", self.pos)
+    }
+
+    fn bytes(&mut self, count: u32) -> Result<&'a [u8], String> {
+        let data: &'a [u8] = self.data;
+        let bytes = self.pos.checked_add(count as usize)
+            .and_then(|end| data.get(self.pos..end))
+            .ok_or_else(|| self.end_of_file())?;
+        self.pos += count as usize;
+        Ok(bytes)
+    }
+
+    /// count longs as bytes. Lengths beyond the address space can't be in the file either.
+    fn longs(&mut self, count: u32) -> Result<&'a [u8], String> {
+        let len = count.checked_mul(4).ok_or_else(|| self.end_of_file())?;
+        self.bytes(len)
+    }
+
+    fn long(&mut self) -> Result<u32, String> {
+        self.bytes(4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn word(&mut self) -> Result<u16, String> {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    /// string of longs * 4 bytes, padded with zeros
+    fn string(&mut self, longs: u32) -> Result<String, String> {
+        let bytes = self.longs(longs)?;
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        Ok(String::from_utf8_lossy(&bytes[..end]).to_string())
+    }
+
+    /// rest of a HUNK_HEADER: (size in bytes, memory flags) per hunk
+    fn header(&mut self) -> Result<Vec<(u32, u32)>, String> {
+        // names of resident libraries, not used anymore
+        loop {
+            let longs = self.long()?;
+            if longs == 0 {
+                break;
+            }
+            self.longs(longs)?;
+        }
+        self.long()?;
+        let first = self.long()?;
+        let last = self.long()?;
+        let mut sizes = Vec::new();
+        for _ in first..=last {
+            let size = self.long()?;
+            let mut flags = size & 0xC0000000;
+            // both flags set: the memory requirements follow in an extra long
+            if flags == 0xC0000000 {
+                flags = self.long()?;
+            }
+            sizes.push(((size & 0x3FFFFFFF) * 4, flags));
+        }
+        Ok(sizes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// executable with a code hunk (JMP to the BSS hunk, relocated, and a symbol) and a BSS hunk
+    /// in chip RAM
+    fn executable() -> Vec<u8> {
+        let longs = [HUNK_HEADER, 0, 2, 0, 1, 2, 0x40000000 | 4,
+            HUNK_CODE, 2, 0x4EF90000, 0x00044E75,
+            HUNK_RELOC32, 1, 1, 2, 0,
+            HUNK_SYMBOL, 1, u32::from_be_bytes(*b"main"), 0, 0,
+            HUNK_END,
+            HUNK_BSS, 4,
+            HUNK_END];
+        longs.iter().flat_map(|l| l.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn parse_hunks() {
+        let exe = HunkFile::parse(&executable()).unwrap();
+        assert_eq!(exe.hunks.len(), 2);
+        let (code, bss) = (&exe.hunks[0], &exe.hunks[1]);
+        assert_eq!((code.kind, code.size, code.memory()), (HunkKind::Code, 8, "any"));
+        assert_eq!((bss.kind, bss.size, bss.memory()), (HunkKind::Bss, 16, "chip"));
+        assert_eq!(code.data, [0x4E, 0xF9, 0x00, 0x00, 0x00, 0x04, 0x4E, 0x75]);
+        assert!(bss.data.is_empty());
+        assert_eq!(code.relocs.iter().collect::<Vec<_>>(), [(&2, &1)]);
+        assert!(code.is_relocated(5) && !code.is_relocated(6));
+        assert_eq!(code.symbols.get(&0).map(String::as_str), Some("main"));
+        // header of 7 longs, HUNK_CODE and its length
+        assert_eq!(code.file_offset, 36);
+        assert_eq!(exe.overlays, 0);
+    }
+
+    #[test]
+    fn addresses_and_file_offsets() {
+        let exe = HunkFile::parse(&executable()).unwrap();
+        let starts = exe.addresses(0x1000);
+        assert_eq!(starts, [0x1000, 0x1008]);
+        assert_eq!(exe.hunk_at(&starts, 0x100C), Some((1, 4)));
+        assert_eq!(exe.hunk_at(&starts, 0x1018), None);
+        assert!(exe.is_relocated_at(&starts, 0x1002));
+        // from the file to memory and back
+        for position in 36..44 {
+            let (hunk, offset) = exe.hunk_at_file_offset(position).unwrap();
+            assert_eq!(exe.hunk_at(&starts, starts[hunk] + offset), Some((hunk, offset)));
+            assert_eq!(exe.hunks[hunk].file_offset + offset, position);
+        }
+        assert_eq!(exe.hunk_at_file_offset(35), None);
+        assert_eq!(exe.hunk_at_file_offset(44), None);
+    }
+
+    #[test]
+    fn locate_in_steps() {
+        // two code hunks calling the same library function, loaded far above unrelated code
+        // calling it many times
+        let longs = [HUNK_HEADER, 0, 2, 0, 1, 3, 3,
+            HUNK_CODE, 3, 0x2C780004, 0x4EAEFFC4, 0x70004E75, HUNK_END,
+            HUNK_CODE, 3, 0x4EAEFF3A, 0x4EAEFFC4, 0x22004E75, HUNK_END];
+        let exe = HunkFile::parse(&longs.iter().flat_map(|l| l.to_be_bytes()).collect::<Vec<_>>()).unwrap();
+        let mut executed: BTreeMap<u32, Vec<u8>> = (0..40)
+            .map(|i| (0x1000 + i * 4, vec![0x4E, 0xAE, 0xFF, 0xC4])).collect();
+        executed.insert(0x20000, vec![0x2C, 0x78, 0x00, 0x04]);
+        executed.insert(0x20004, vec![0x4E, 0xAE, 0xFF, 0xC4]);
+        executed.insert(0x20008, vec![0x70, 0x00]);
+        let found = exe.hunks[0].locate_in_steps(&executed).unwrap();
+        assert_eq!((found.address, found.matching, found.differing, found.candidates), (0x20000, 3, 0, 1));
+        // the unrelated code matches the second hunk only in part
+        assert!(exe.hunks[1].locate_in_steps(&executed).is_none());
+        executed.insert(0x30000, vec![0x4E, 0xAE, 0xFF, 0x3A]);
+        executed.insert(0x30004, vec![0x4E, 0xAE, 0xFF, 0xC4]);
+        let found = exe.hunks[1].locate_in_steps(&executed).unwrap();
+        assert_eq!((found.address, found.matching, found.differing, found.candidates), (0x30000, 2, 0, 1));
+    }
+
+    #[test]
+    fn not_an_executable() {
+        assert!(HunkFile::parse(&[0, 0, 3, 0xE7]).is_err());
+        assert!(HunkFile::parse(&executable()[..40]).is_err());
+    }
+
+    #[test]
+    fn huge_lengths() {
+        let header = [HUNK_HEADER, 0, 1, 0, 0, 1];
+        for block in [[HUNK_NAME, 0x40000000], [HUNK_DEBUG, 0x40000001], [HUNK_OVERLAY, 0xFFFFFFFF],
+            [HUNK_CODE, 0x3FFFFFFF]].iter() {
+            let longs: Vec<u32> = header.iter().chain(block).copied().collect();
+            let data: Vec<u8> = longs.iter().flat_map(|l| l.to_be_bytes()).collect();
+            assert_eq!(HunkFile::parse(&data).err(), Some("unexpected end of file at 20".to_string()));
+        }
+        // name of a resident library
+        let data: Vec<u8> = [HUNK_HEADER, 0x40000000].iter().flat_map(|l| l.to_be_bytes()).collect();
+        assert_eq!(HunkFile::parse(&data).err(), Some("unexpected end of file at 8".to_string()));
+    }
+}
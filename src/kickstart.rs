@@ -0,0 +1,254 @@
+/*
+    Copyright (C) 2020 Arkadiusz Guzinski <kermit@ag.de1.cc>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use crate::lvo::Libraries;
+use crate::memdump::MemDump;
+
+/// well known Kickstart ROMs: (CRC32, id used for the symbol map file name, description)
+const KNOWN_ROMS: [(u32, &str, &str); 12] = [
+    (0xA6CE1636, "kick33180", "Kickstart 1.2 (33.180)"),
+    (0xC4F0F55F, "kick34005", "Kickstart 1.3 (34.5)"),
+    (0xC3BDB240, "kick37175", "Kickstart 2.04 (37.175) A500+"),
+    (0x83028FB5, "kick37299", "Kickstart 2.05 (37.299) A600"),
+    (0x64466C2A, "kick37300", "Kickstart 2.05 (37.300) A600HD"),
+    (0x43B0DF7B, "kick37350", "Kickstart 2.05 (37.350) A600HD"),
+    (0x6C9B07D2, "kick39106-a1200", "Kickstart 3.0 (39.106) A1200"),
+    (0x9E6AC152, "kick39106-a4000", "Kickstart 3.0 (39.106) A4000"),
+    (0xFC24AE0D, "kick40063", "Kickstart 3.1 (40.63) A500/A600/A2000"),
+    (0x1483A091, "kick40068-a1200", "Kickstart 3.1 (40.68) A1200"),
+    (0xD6BAE334, "kick40068-a4000", "Kickstart 3.1 (40.68) A4000"),
+    (0x1E62D4A5, "kick40060-cd32", "Kickstart 3.1 (40.60) CD32"),
+];
+
+/// RTC_MATCHWORD, the first word of a struct Resident
+const RTC_MATCHWORD: u16 = 0x4AFC;
+/// resident flag: rt_Init points to a table for MakeLibrary
+const RTF_AUTOINIT: u8 = 0x80;
+
+/// a Kickstart ROM image and the names of its routines
+pub struct Kickstart {
+    /// name of the symbol map file (without .sym)
+    pub id: String,
+    pub description: String,
+    /// address the ROM is mapped at
+    pub base: u32,
+    pub data: Vec<u8>,
+    /// entry address -> name
+    pub symbols: BTreeMap<u32, String>,
+}
+
+impl Kickstart {
+    /// load ROM image from path. 256K images are mapped at 00FC0000, 512K images at 00F80000.
+    ///
+    /// Symbols are taken from the resident modules in the ROM and their library function tables
+    /// (named using libs), and from the symbol map [id].sym or [file name].sym next to the image.
+    pub fn load(path: &Path, libs: &Libraries) -> io::Result<Kickstart> {
+        let data = fs::read(path)?;
+        if data.starts_with(b"AMIROMTYPE1") {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "encrypted ROM images are not supported"));
+        }
+        let base = match data.len() {
+            0x40000 => 0x00FC0000,
+            0x80000 => 0x00F80000,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "ROM image must be 256K or 512K"))
+        };
+        let crc = crc32(&data);
+        let (id, description) = match KNOWN_ROMS.iter().find(|(c, _, _)| *c == crc) {
+            Some((_, id, description)) => (id.to_string(), description.to_string()),
+            None => {
+                // version and revision are in the ROM header
+                let word = |at: usize| u16::from_be_bytes([data[at], data[at + 1]]);
+                (format!("kick{}{:03}", word(12), word(14)),
+                 format!("Kickstart {}.{} (unknown, CRC32 {:08X})", word(12), word(14), crc))
+            }
+        };
+        if !Kickstart::checksum_ok(&data) {
+            eprintln!("warning: {} has an invalid checksum", path.display());
+        }
+        let mut rom = Kickstart { id, description, base, data, symbols: BTreeMap::new() };
+        rom.resident_symbols(libs);
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        for sym in [format!("{}.sym", rom.id), format!("{}.sym", name)].iter() {
+            if let Ok(file) = File::open(dir.join(sym)) {
+                rom.load_symbols(file);
+                break;
+            }
+        }
+        Ok(rom)
+    }
+
+    /// does the Kickstart checksum (sum of all longs with end around carry) match?
+    pub fn checksum_ok(data: &[u8]) -> bool {
+        let mut sum: u32 = 0;
+        for long in data.chunks_exact(4) {
+            let (s, carry) = sum.overflowing_add(u32::from_be_bytes([long[0], long[1], long[2], long[3]]));
+            sum = s + carry as u32;
+        }
+        sum == 0xFFFFFFFF
+    }
+
+    /// read symbols from a symbol map: one address (hex) and name per line. Empty lines and lines
+    /// starting with '#' are ignored. Symbols from the map replace those found in the ROM.
+    pub fn load_symbols(&mut self, file: File) {
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let address = parts.next().map(|a| a.trim_start_matches('$').trim_start_matches("0x"))
+                .and_then(|a| u32::from_str_radix(a, 16).ok());
+            match (address, parts.next()) {
+                (Some(address), Some(name)) => { self.symbols.insert(address, name.to_string()); }
+                _ => eprintln!("ignoring invalid line in symbol map: {}", line)
+            }
+        }
+    }
+
+    /// name the init routines of the resident modules and the functions of auto-init libraries
+    fn resident_symbols(&mut self, libs: &Libraries) {
+        for offset in (0..self.data.len().saturating_sub(26)).step_by(2) {
+            let address = self.base + offset as u32;
+            if self.word(address) != Some(RTC_MATCHWORD) || self.long(address + 2) != Some(address) {
+                continue;
+            }
+            let name = match self.long(address + 14).and_then(|a| self.string(a)) {
+                Some(name) => name,
+                None => continue
+            };
+            let flags = self.data[offset + 10];
+            let init = match self.long(address + 22) {
+                Some(init) => init,
+                None => continue
+            };
+            if flags & RTF_AUTOINIT == 0 {
+                self.symbols.entry(init).or_insert_with(|| format!("{}_Init", name));
+                continue;
+            }
+            // auto-init table: data size, function table, data table, init routine
+            if let Some(routine) = self.long(init + 12).filter(|r| *r != 0) {
+                self.symbols.entry(routine).or_insert_with(|| format!("{}_Init", name));
+            }
+            if let Some(table) = self.long(init + 4) {
+                let lib = name.trim_end_matches(".library").trim_end_matches(".device").to_string();
+                let fd = libs.find(&lib).map(|l| &libs.libs[l]);
+                for (k, function) in self.function_table(table).into_iter().enumerate() {
+                    // the table starts with Open, Close, Expunge and Reserved at -6, -12, ...
+                    let lvo = 6 * (k as u16 + 1);
+                    let fname = match (lvo, fd.and_then(|l| l.functions.get(&lvo))) {
+                        (_, Some(f)) => f.name.clone(),
+                        (6, None) => "Open".to_string(),
+                        (12, None) => "Close".to_string(),
+                        (18, None) => "Expunge".to_string(),
+                        (24, None) => "Reserved".to_string(),
+                        _ => format!("LVO_{}", lvo)
+                    };
+                    self.symbols.entry(function).or_insert_with(|| format!("{}.{}", lib, fname));
+                }
+            }
+        }
+    }
+
+    /// addresses in a function table for MakeFunctions: absolute longs terminated by -1 or, if the
+    /// table starts with -1 (word), offsets relative to the table terminated by -1 (word)
+    fn function_table(&self, table: u32) -> Vec<u32> {
+        let mut functions = Vec::new();
+        if self.word(table) == Some(0xFFFF) {
+            let mut at = table + 2;
+            while let Some(offset) = self.word(at).filter(|w| *w != 0xFFFF) {
+                functions.push(table.wrapping_add(offset as i16 as u32));
+                at += 2;
+            }
+        } else {
+            let mut at = table;
+            while let Some(function) = self.long(at).filter(|l| *l != 0xFFFFFFFF) {
+                functions.push(function);
+                at += 4;
+            }
+        }
+        functions
+    }
+
+    /// does the ROM contain address?
+    pub fn contains(&self, address: u32) -> bool {
+        address >= self.base && ((address - self.base) as usize) < self.data.len()
+    }
+
+    fn bytes(&self, address: u32, count: usize) -> Option<&[u8]> {
+        if !self.contains(address) {
+            return None;
+        }
+        let at = (address - self.base) as usize;
+        self.data.get(at..at + count)
+    }
+
+    fn word(&self, address: u32) -> Option<u16> {
+        self.bytes(address, 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn long(&self, address: u32) -> Option<u32> {
+        self.bytes(address, 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// zero terminated string at address, without trailing line breaks
+    fn string(&self, address: u32) -> Option<String> {
+        let at = (address.checked_sub(self.base)?) as usize;
+        let end = self.data.get(at..)?.iter().take(64).position(|b| *b == 0)?;
+        std::str::from_utf8(&self.data[at..at + end]).ok().map(|s| s.trim_end().to_string())
+    }
+
+    /// entry of the symbol at or before pc, if pc is inside the ROM
+    pub fn function_start(&self, pc: u32) -> Option<u32> {
+        if !self.contains(pc) {
+            return None;
+        }
+        self.symbols.range(..=pc).next_back().map(|(start, _)| *start)
+    }
+
+    /// name of the symbol at or before pc, with the distance to it, e.g. "exec.AllocMem+$1C"
+    pub fn name_for(&self, pc: u32) -> Option<String> {
+        let start = self.function_start(pc)?;
+        let name = &self.symbols[&start];
+        match pc - start {
+            0 => Some(name.clone()),
+            distance => Some(format!("{}+${:X}", name, distance))
+        }
+    }
+
+    /// add the ROM to mem, unless mem already contains its first address
+    pub fn map_to(&self, mem: &mut MemDump) {
+        if mem.long_at(self.base).is_none() {
+            mem.add_part(self.base, self.data.clone());
+        }
+    }
+}
+
+/// CRC32 (as used by zip), used to identify ROM images
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
@@ -0,0 +1,184 @@
+/*
+    Copyright (C) 2020 Arkadiusz Guzinski <kermit@ag.de1.cc>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::collections::BTreeMap;
+use crate::cpustep::CpuStep;
+use crate::operand::{Operand, Size};
+
+/// .fd files of the standard libraries, built into the program: (library name, content)
+const BUILTIN_FD: [(&str, &str); 6] = [
+    ("exec", include_str!("../fd/exec_lib.fd")),
+    ("dos", include_str!("../fd/dos_lib.fd")),
+    ("graphics", include_str!("../fd/graphics_lib.fd")),
+    ("intuition", include_str!("../fd/intuition_lib.fd")),
+    ("layers", include_str!("../fd/layers_lib.fd")),
+    ("mathffp", include_str!("../fd/mathffp_lib.fd")),
+];
+
+/// offset of OpenLibrary in exec.library
+pub const LVO_OPEN_LIBRARY: u16 = 552;
+/// offset of OldOpenLibrary in exec.library
+pub const LVO_OLD_OPEN_LIBRARY: u16 = 408;
+
+/// flags for AllocMem and similar
+const MEMF_FLAGS: [(u32, &str); 11] = [
+    (0x00000001, "MEMF_PUBLIC"),
+    (0x00000002, "MEMF_CHIP"),
+    (0x00000004, "MEMF_FAST"),
+    (0x00000100, "MEMF_LOCAL"),
+    (0x00000200, "MEMF_24BITDMA"),
+    (0x00000400, "MEMF_KICK"),
+    (0x00010000, "MEMF_CLEAR"),
+    (0x00020000, "MEMF_LARGEST"),
+    (0x00040000, "MEMF_REVERSE"),
+    (0x00080000, "MEMF_TOTAL"),
+    (0x80000000, "MEMF_NO_EXPUNGE"),
+];
+
+/// a library function, as described in a .fd file
+pub struct LibFunction {
+    pub name: String,
+    /// (argument name, register), e.g. ("byteSize", "D0")
+    pub args: Vec<(String, String)>,
+}
+
+/// function table of a library: negative offset (LVO) -> function
+pub struct Library {
+    pub name: String,
+    pub functions: BTreeMap<u16, LibFunction>,
+}
+
+impl Library {
+    /// parse the content of a .fd file
+    pub fn from_fd(name: &str, content: &str) -> Library {
+        let mut functions = BTreeMap::new();
+        let mut offset: u16 = 30;
+        for line in content.lines() {
+            let line = line.trim();
+            if let Some(bias) = line.strip_prefix("##bias") {
+                offset = bias.trim().parse().unwrap_or(offset);
+                continue;
+            }
+            if line.is_empty() || line.starts_with('*') || line.starts_with("##") {
+                continue;
+            }
+            // Name(arg1,arg2)(reg1/reg2)
+            let mut parts = line.split(['(', ')']).map(str::trim);
+            let fname = parts.next().unwrap_or_default();
+            let arg_names = parts.next().unwrap_or_default();
+            parts.next();
+            let regs = parts.next().unwrap_or_default();
+            let args = arg_names.split(',')
+                .zip(regs.split([',', '/']))
+                .filter(|(a, r)| !a.is_empty() && !r.is_empty())
+                .map(|(a, r)| (a.to_string(), r.to_uppercase()))
+                .collect();
+            functions.insert(offset, LibFunction { name: fname.to_string(), args });
+            offset += 6;
+        }
+        Library { name: name.to_string(), functions }
+    }
+}
+
+/// known libraries and the base addresses they were found at
+pub struct Libraries {
+    pub libs: Vec<Library>,
+    /// library base -> index into libs
+    pub bases: BTreeMap<u32, usize>,
+}
+
+impl Libraries {
+    /// create with the built in function tables
+    pub fn new() -> Libraries {
+        Libraries {
+            libs: BUILTIN_FD.iter().map(|(name, fd)| Library::from_fd(name, fd)).collect(),
+            bases: BTreeMap::new(),
+        }
+    }
+
+    /// find library by name, with or without ".library" suffix
+    pub fn find(&self, name: &str) -> Option<usize> {
+        let name = name.trim_end_matches(".library");
+        self.libs.iter().position(|l| l.name == name)
+    }
+
+    /// remember that library name has its base at address base
+    pub fn add_base(&mut self, base: u32, name: &str) -> bool {
+        match self.find(name) {
+            Some(idx) => {
+                self.bases.insert(base, idx);
+                true
+            }
+            None => false
+        }
+    }
+
+    /// library offset of a JSR through A6 at step, e.g. 198 for "JSR (A6, -$00c6)"
+    pub fn call_offset(step: &CpuStep) -> Option<u16> {
+        if step.mnemonic() != "JSR" {
+            return None;
+        }
+        match step.operands().first()?.displacement()? {
+            (6, disp) if disp < 0 => Some((-disp) as u16),
+            _ => None
+        }
+    }
+
+    /// library function called by step, if it is a JSR through a known library base in A6
+    pub fn function_at(&self, step: &CpuStep) -> Option<(&Library, &LibFunction)> {
+        let offset = Libraries::call_offset(step)?;
+        let lib = &self.libs[*self.bases.get(&step.address[6])?];
+        Some((lib, lib.functions.get(&offset)?))
+    }
+
+    /// describe library call at step, e.g. "exec.AllocMem(D0=$1000, D1=MEMF_CHIP)"
+    pub fn annotate(&self, step: &CpuStep) -> Option<String> {
+        let (lib, function) = self.function_at(step)?;
+        let args: Vec<String> = function.args.iter()
+            .map(|(name, reg)| format!("{}={}", reg, Libraries::arg_value(&lib.name, name, reg, step)))
+            .collect();
+        Some(format!("{}.{}({})", lib.name, function.name, args.join(", ")))
+    }
+
+    /// value of register reg at step, decoded by argument name where the meaning is known
+    fn arg_value(lib: &str, name: &str, reg: &str, step: &CpuStep) -> String {
+        let val = match Operand::parse(reg).value(step, Size::Long) {
+            Some(val) => val,
+            None => return "?".to_string()
+        };
+        match (lib, name) {
+            ("exec", "requirements") | ("exec", "attributes") => {
+                let flags: Vec<&str> = MEMF_FLAGS.iter()
+                    .filter(|(f, _)| val & f != 0)
+                    .map(|(_, n)| *n)
+                    .collect();
+                let rest = MEMF_FLAGS.iter().fold(val, |v, (f, _)| v & !f);
+                match (flags.is_empty(), rest) {
+                    (true, 0) => "MEMF_ANY".to_string(),
+                    (false, 0) => flags.join("|"),
+                    _ => format!("${:X}", val)
+                }
+            }
+            ("dos", "accessMode") => match val {
+                1004 => "MODE_READWRITE".to_string(),
+                1005 => "MODE_OLDFILE".to_string(),
+                1006 => "MODE_NEWFILE".to_string(),
+                _ => format!("${:X}", val)
+            },
+            _ => format!("${:X}", val)
+        }
+    }
+}
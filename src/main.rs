@@ -14,9 +14,29 @@
     You should have received a copy of the GNU General Public License
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
+mod addressset;
+mod callgraph;
+mod context;
+mod coverage;
 mod cpustep;
 mod dump;
+mod exception;
+mod filter;
+mod hardware;
+mod hunk;
+mod kickstart;
+mod lvo;
+mod memdiff;
 mod memdump;
+mod memsearch;
+mod operand;
+mod pattern;
+mod profile;
+mod ranges;
+mod savestate;
+mod segments;
+mod shadowstack;
+mod structs;
 mod utils;
 mod cli;
 
@@ -26,10 +46,20 @@ extern crate serde_derive;
 extern crate serde_big_array;
 
 use std::{fs};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
 use crate::dump::Dump;
+use crate::addressset::AddressSet;
+use crate::memdiff::DiffGroup;
 use crate::memdump::MemDump;
+use crate::memsearch::{Encoding, Relation, SearchSession};
+use crate::hunk::{HunkFile, HunkKind, HunkMatch};
+use crate::segments::{HunkSegment, SegmentMap};
+use crate::savestate::SaveState;
+use crate::structs::{FieldType, Structs};
 use crate::utils::{FormatHelper};
-use std::collections::{BTreeMap, HashMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet};
 use core::cmp;
 use clap::{ArgMatches};
 
@@ -43,13 +73,25 @@ fn main() -> std::io::Result<()> {
     let matches = cli::args().get_matches();
 
     match matches.subcommand() {
+        Some(("callgraph", sub_args)) => call_graph(sub_args),
         Some(("calls", sub_args)) => show_calls(&sub_args),
+        Some(("comparisons", sub_args)) => comparisons(sub_args),
+        Some(("coverage", sub_args)) => coverage(sub_args),
+        Some(("exceptions", sub_args)) => exceptions(sub_args),
+        Some(("find-offset", sub_args)) => find_offset(sub_args),
         Some(("help-fs", _)) => print_help_fs(),
+        Some(("hunks", sub_args)) => hunks(sub_args),
+        Some(("hw-log", sub_args)) => hw_log(sub_args),
+        Some(("interrupts", sub_args)) => interrupts(sub_args),
         Some(("map-data", sub_args)) => map_data_to_mem(&sub_args),
+        Some(("mem-view", sub_args)) => mem_view(sub_args),
+        Some(("memsearch", sub_args)) => mem_search(sub_args),
         Some(("memset-diff", sub_args)) => mem_set_diff(&sub_args),
         Some(("print-ghidra-search-pattern", sub_args)) => print_ghidra_search_pattern(&sub_args),
         Some(("print-mem-commands", sub_args)) => print_mem_commands(&sub_args), // get mem info commands :: dump pc num_before
+        Some(("profile", sub_args)) => profile(sub_args),
         Some(("registers", sub_args)) => in_out_state(&sub_args),
+        Some(("savestate", sub_args)) => savestate(sub_args),
         Some(("search-value", sub_args)) => search_value(&sub_args), // search for value in dump :: dir val [dir val] ..
         Some(("stack", sub_args)) => stack(&sub_args),
         Some(("starting-pcs", sub_args)) => print_starting_pcs(&sub_args),
@@ -68,20 +110,44 @@ fn print_help_fs() {
          opcode.bin   is the above, preprocessed to a binary format for faster loading.
              it may be necessary to delete after an update of the program or opcode.log\n\
          offset       file containing only one hex value that can be subtracted from the program
-             counter to print the direct address for Ghidra or similar tools.\n\
+             counter to print the direct address for Ghidra or similar tools. The find-offset
+             command can determine it from the executable.\n\
+         segments     address ranges of the hunks of the program, one per line: hunk number, first
+             and last address in the dump and address in Ghidra (hex), e.g.
+             \"1 07200000 072003FF 00221000\". Addresses inside a segment are translated relative to
+             it, all others using offset. Written by the find-offset command.\n\
          mem          memory dump in text form\n\
          [0-9,A-F]{8} binary memory dump - the preferred way.. the name is a 8-digit hexadecimal
              value, equal to the starting address, e.g. 00000000 or 07000000\n\
-         functions.xml  Ghidra xml export, containing function information.\n\
+         *.uss        UAE savestate; its RAM (and saved ROMs) are used as memory dump, if there are
+             no binary dumps. See the savestate command.\n\
+         functions.xml  Ghidra xml export, containing function and label information.\n\
+         ranges       address ranges of ROM, OS and program code, one per line: kind (rom, os or
+             program), first and last address (hex), e.g. \"os 00C00000 00C3FFFF\". Unless defined
+             otherwise, 00E00000-00E7FFFF and 00F80000-00FFFFFF are ROM, everything else is program.\n\
+         structs      structure descriptions for the mem-view command: \"struct Name\", followed by one
+             line per field with optional offset (hex), type and name, e.g. \"+1A word x\". See README.\n\
 \n\
-offset and functions.xml will be used from parent of dir, if not found\n\
+         kickstart.rom  Kickstart ROM image (256K or 512K, not encrypted), mapped to memory and used for
+             names of ROM routines. They are found in the ROM and read from a symbol map next to
+             the image, named after the ROM version (e.g. kick40068-a1200.sym, see the message
+             when loading) or the image (kickstart.rom.sym), with one address (hex) and name per
+             line.\n\
+\n\
+offset, segments, functions.xml, ranges, structs and kickstart.rom will be used from parent of dir, if not found\n\
 \n\
     For the memset-diff command, set_dir expects a directory, containing directories with memory\n\
     dumps, all applying to the same range of memory. These directories are named set_id, where
     set          is the name of the set
     id           can be anything not containing underscores\n\
     Then the command will print the positions of values that differ between all memory dumps of\n\
-    different sets, but are equal within a set."
+    different sets, but are equal within a set. Only address ranges contained in all dumps are\n\
+    compared, bytes outside of them are reported as ignored. Consecutive bytes are grouped and\n\
+    shown with the values of each set and the Ghidra label (from functions.xml) or hunk they are\n\
+    in; -f csv or json write the same for spreadsheets and scripts, -f list one address per line.\n\
+    If the value of each set is known, give it with -v set=value (e.g. -v gold100=100 gold250=250)\n\
+    to print only the addresses where all dumps of each set hold exactly that value, as big endian\n\
+    8, 16 or 32 bit value or BCD (see -e)."
     )
 }
 
@@ -127,38 +193,376 @@ fn summary(args: &ArgMatches, short: bool) {
     let num_before = usize::from_str_radix(args.value_of("count").unwrap(), 10).unwrap();
     let highlight = args.values_of("val").unwrap_or_default();
 
-    let fmt = if short {
+    let mut fmt = if short {
         FormatHelper::simple(true)
     } else {
         FormatHelper::for_values(&highlight, false)
     }.finalize(args);
-    let mem: MemDump = match MemDump::from_dir(path.to_string()) {
-        Ok(m) => m,
-        Err(_) => MemDump::new()
-    };
-    Dump::from_dir(path.to_string()).expect("could not load dump")
-        .inspect(mem, pc, num_before, fmt).expect("summary failed");
+    let mem = load_mem(path, &fmt);
+    let dump = Dump::from_dir(path.to_string()).expect("could not load dump");
+    dump.find_libraries(&mem, &mut fmt.libs);
+    dump.inspect(mem, pc, num_before, fmt).expect("summary failed");
+}
+
+/// load memory dump from dir (empty, if there is none) and add the Kickstart ROM
+fn load_mem(path: &str, fmt: &FormatHelper) -> MemDump {
+    let mut mem = MemDump::from_dir(path.to_string()).unwrap_or_else(|_| MemDump::new());
+    if let Some(rom) = &fmt.kickstart {
+        rom.map_to(&mut mem);
+    }
+    mem
 }
 
 /// print call hierarchy leading to pc
 fn stack(args: &ArgMatches) {
     let path = args.value_of("dir").unwrap();
     let pc = u32::from_str_radix(args.value_of("pc").unwrap(), 16).unwrap();
-    let fmt = FormatHelper::simple(true).finalize(args);
+    let mut fmt = FormatHelper::simple(true).finalize(args);
+    let mem = load_mem(path, &fmt);
 
-    Dump::from_dir(path.to_string()).expect("could not load dump").stack(pc, fmt)
+    let dump = Dump::from_dir(path.to_string()).expect("could not load dump");
+    dump.find_libraries(&mem, &mut fmt.libs);
+    dump.stack(pc, &mem, fmt)
         .expect("failed reading dump ");
 }
 
 /// print complete call hierarchy
 fn show_calls(args: &ArgMatches) {
     let path = args.value_of("dir").unwrap();
-    let fmt = FormatHelper::simple(true).finalize(args);
+    let mut fmt = FormatHelper::simple(true).finalize(args);
+    let mem = load_mem(path, &fmt);
 
-    Dump::from_dir(path.to_string()).expect("could not load dump").calls(fmt)
+    let dump = Dump::from_dir(path.to_string()).expect("could not load dump");
+    dump.find_libraries(&mem, &mut fmt.libs);
+    dump.calls(&mem, fmt)
         .expect("failed reading dump ");
 }
 
+/// print exceptions with handler and return point
+fn exceptions(args: &ArgMatches) {
+    let path = args.value_of("dir").unwrap();
+    let fmt = FormatHelper::simple(true).finalize(args);
+    let mem = load_mem(path, &fmt);
+
+    Dump::from_dir(path.to_string()).expect("could not load dump").exceptions(&mem, &fmt);
+}
+
+/// print interrupt handler invocations with their duration
+fn interrupts(args: &ArgMatches) {
+    let path = args.value_of("dir").unwrap();
+    let fmt = FormatHelper::simple(true).finalize(args);
+    let mem = load_mem(path, &fmt);
+
+    Dump::from_dir(path.to_string()).expect("could not load dump").interrupts(&mem, &fmt);
+}
+
+/// print writes to custom chip and CIA registers
+fn hw_log(args: &ArgMatches) {
+    let path = args.value_of("dir").unwrap();
+    let fmt = FormatHelper::simple(true).finalize(args);
+
+    Dump::from_dir(path.to_string()).expect("could not load dump").hw_log(&fmt);
+}
+
+/// export aggregated call graph
+fn call_graph(args: &ArgMatches) {
+    let path = args.value_of("dir").unwrap();
+    let fmt = FormatHelper::simple(true).finalize(args);
+
+    let mut graph = Dump::from_dir(path.to_string()).expect("could not load dump").call_graph(&fmt);
+    if let Some(from) = args.value_of("from") {
+        match graph.find_node(from, &fmt) {
+            Some(root) => graph = graph.reachable_from(root),
+            None => {
+                eprintln!("function {} not found in call graph", from);
+                return;
+            }
+        }
+    }
+    match args.value_of("format") {
+        Some("graphml") => println!("{}", graph.to_graphml(&fmt)),
+        _ => println!("{}", graph.to_dot(&fmt))
+    }
+}
+
+/// print instructions executed per call stack or function
+fn profile(args: &ArgMatches) {
+    let path = args.value_of("dir").unwrap();
+    let fmt = FormatHelper::simple(true).finalize(args);
+
+    let profile = Dump::from_dir(path.to_string()).expect("could not load dump").profile(&fmt);
+    match args.value_of("format") {
+        Some("table") => profile.print_table(&fmt),
+        _ => profile.print_folded(&fmt)
+    }
+}
+
+/// write executed instruction addresses, optionally only those not executed in other dumps
+fn coverage(args: &ArgMatches) {
+    let path = args.value_of("dir").unwrap();
+    let mut fmt = FormatHelper::simple(true).finalize(args);
+
+    let mut coverage = Dump::from_dir(path.to_string()).expect("could not load dump").coverage(&fmt);
+    for other in args.values_of("without").unwrap_or_default() {
+        // the program may have been loaded elsewhere in the other dump
+        if args.value_of("offset-mode") != Some("dump") {
+            fmt.segments = FormatHelper::segments_of(other);
+        }
+        coverage.subtract(&Dump::from_dir(other.to_string()).expect("could not load dump").coverage(&fmt));
+    }
+
+    let mut out: Box<dyn Write> = match args.value_of("out") {
+        Some(file) => Box::new(BufWriter::new(File::create(file).expect("could not create output file"))),
+        None => Box::new(std::io::stdout())
+    };
+    match args.value_of("format") {
+        Some("drcov") => coverage.write_drcov(args.value_of("module").unwrap_or("program"), &mut out),
+        _ => coverage.write_list(&mut out)
+    }.expect("writing coverage failed");
+}
+
+/// locate the hunks of an executable in a dump by the executed instructions (code hunks) or the
+/// memory dump, and write the difference to the addresses in Ghidra to dir/offset and the
+/// hunks found to dir/segments
+fn find_offset(args: &ArgMatches) {
+    let path = args.value_of("dir").unwrap();
+    let exe = HunkFile::from_file(Path::new(args.value_of("exe").unwrap())).expect("could not load executable");
+    let base = u32::from_str_radix(args.value_of("base").unwrap_or("21F000"), 16).expect("invalid base address");
+    let dump = Dump::from_dir(path.to_string()).expect("could not load dump");
+    let mem = MemDump::from_dir(path.to_string()).unwrap_or_else(|_| MemDump::new());
+    let executed = dump.executed_code();
+
+    let addresses = exe.addresses(base);
+    let found: Vec<Option<(HunkMatch, &str)>> = exe.hunks.iter().map(|hunk| {
+        let by_steps = match hunk.kind {
+            HunkKind::Code => hunk.locate_in_steps(&executed).filter(|m| m.matching > 0),
+            _ => None
+        };
+        match by_steps {
+            Some(m) => Some((m, "instructions")),
+            None if hunk.kind != HunkKind::Bss => hunk.locate_in_mem(&mem).map(|m| (m, "bytes in memory")),
+            None => None
+        }
+    }).collect();
+    // the relocated longs of the hunks found tell where the hunks they point to were loaded, which
+    // also locates BSS hunks and hunks whose content changed
+    let mut by_relocs: BTreeMap<usize, (u32, usize)> = BTreeMap::new();
+    for (hunk, found) in exe.hunks.iter().zip(&found) {
+        if let Some((m, _)) = found.as_ref().filter(|(m, _)| m.candidates == 1) {
+            for (target, (address, count)) in hunk.reloc_targets(m.address, &mem) {
+                if by_relocs.get(&target).is_none_or(|(_, best)| count > *best) {
+                    by_relocs.insert(target, (address, count));
+                }
+            }
+        }
+    }
+
+    println!("Hunk\tType\tSize\tGhidra\tLoaded\tOffset\tMatch");
+    // hunk index, offset and number of matching instructions of the hunk that decides the offset
+    let mut best: Option<(usize, u32, usize)> = None;
+    let mut offsets = BTreeSet::new();
+    let mut segments = SegmentMap::new(0);
+    for (i, (hunk, found)) in exe.hunks.iter().zip(found).enumerate() {
+        let ghidra = addresses[i];
+        print!("{}\t{}\t{:08X}\t{:08X}\t", i, hunk.kind, hunk.size, ghidra);
+        let relocated = by_relocs.get(&i).copied().filter(|(address, _)| address.checked_add(hunk.size).is_some());
+        let address = match (found, relocated) {
+            (Some((found, unit)), _) => {
+                let offset = found.address.wrapping_sub(ghidra);
+                offsets.insert(offset);
+                print!("{:08X}\t{:08X}\t{} of {} {} ({:.1}%)", found.address, offset, found.matching,
+                       found.matching + found.differing, unit, found.confidence());
+                if found.candidates > 1 {
+                    print!(", {} equally good addresses", found.candidates);
+                }
+                if unit == "instructions" && mem.bytes_at(found.address, 1).is_some() {
+                    let check = hunk.compare_with_mem(found.address, &mem);
+                    print!(", {} of {} bytes in memory", check.matching, check.matching + check.differing);
+                }
+                match relocated {
+                    Some((address, _)) if address != found.address => print!(", relocations point to {:08X}", address),
+                    _ => {}
+                }
+                println!();
+                // code hunks found by executed instructions are the most reliable
+                let weight = if unit == "instructions" { found.matching * 1000 } else { found.matching };
+                if found.candidates == 1 && best.is_none_or(|(_, _, w)| weight > w) {
+                    best = Some((i, offset, weight));
+                }
+                match relocated {
+                    _ if found.candidates == 1 => found.address,
+                    Some((address, _)) => address,
+                    None => continue
+                }
+            }
+            (None, Some((address, count))) => {
+                let offset = address.wrapping_sub(ghidra);
+                offsets.insert(offset);
+                println!("{:08X}\t{:08X}\t{} relocations", address, offset, count);
+                address
+            }
+            (None, None) => {
+                println!("-\t-\tnot found");
+                continue;
+            }
+        };
+        segments.segments.push(HunkSegment { hunk: i, first: address, last: address + hunk.size.max(1) - 1, ghidra });
+    }
+    if offsets.len() > 1 {
+        println!("hunks were not loaded one after another, offsets differ per hunk");
+    }
+    match best {
+        Some((hunk, offset, _)) if args.is_present("dry-run") => println!("offset {:X} (hunk {})", offset, hunk),
+        Some((hunk, offset, _)) => {
+            let file = Path::new(path).join("offset");
+            fs::write(&file, format!("{:08X}\n", offset)).expect("could not write offset file");
+            println!("offset {:X} (hunk {}) written to {}", offset, hunk, file.display());
+            let file = Path::new(path).join("segments");
+            segments.write(&mut File::create(&file).expect("could not create segments file"))
+                .expect("could not write segments file");
+            println!("{} segments written to {}", segments.segments.len(), file.display());
+        }
+        None => println!("no hunk found unambiguously, offset file not written")
+    }
+}
+
+/// list the hunks of an executable with their addresses in Ghidra and the dump, and translate
+/// addresses in the dump to offsets in the file and back
+fn hunks(args: &ArgMatches) {
+    let exe = HunkFile::from_file(Path::new(args.value_of("exe").unwrap())).expect("could not load executable");
+    let base = u32::from_str_radix(args.value_of("base").unwrap_or("21F000"), 16).expect("invalid base address");
+    let segments = FormatHelper::get_segments(args);
+    let ghidra = exe.addresses(base);
+    let loaded: Vec<u32> = ghidra.iter().map(|g| segments.to_dump(*g)).collect();
+
+    println!("Hunk\tType\tMemory\tSize\tFile\tRelocs\tSymbols\tOverlay\tGhidra\tDump\tName");
+    for (i, hunk) in exe.hunks.iter().enumerate() {
+        println!("{}\t{}\t{}\t{:08X}\t{:08X}\t{}\t{}\t{}\t{:08X}\t{:08X}\t{}", i, hunk.kind, hunk.memory(),
+                 hunk.size, hunk.file_offset, hunk.relocs.len(), hunk.symbols.len(),
+                 hunk.overlay.map_or("-".to_string(), |node| node.to_string()), ghidra[i], loaded[i],
+                 hunk.name.as_deref().unwrap_or("-"));
+    }
+    if exe.overlays > 0 {
+        println!("overlay nodes: {} (their hunks share memory at runtime)", exe.overlays);
+    }
+    if args.is_present("symbols") {
+        println!("\nHunk\tOffset\tGhidra\tDump\tSymbol");
+        for (i, hunk) in exe.hunks.iter().enumerate() {
+            for (offset, name) in &hunk.symbols {
+                println!("{}\t{:08X}\t{:08X}\t{:08X}\t{}", i, offset, ghidra[i] + offset, loaded[i] + offset, name);
+            }
+        }
+    }
+
+    for address in args.values_of("address").unwrap_or_default() {
+        let address = u32::from_str_radix(address, 16).expect("invalid address");
+        match exe.hunk_at(&loaded, address) {
+            Some((i, offset)) if (offset as usize) < exe.hunks[i].data.len() =>
+                println!("{:08X} -> hunk {} +${:X}, file offset ${:X}", address, i, offset,
+                         exe.hunks[i].file_offset + offset),
+            Some((i, offset)) => println!("{:08X} -> hunk {} +${:X}, not in the file (zero filled)", address, i, offset),
+            None => println!("{:08X} -> not inside a hunk", address)
+        }
+    }
+    for position in args.values_of("file-offset").unwrap_or_default() {
+        let position = u32::from_str_radix(position, 16).expect("invalid file offset");
+        match exe.hunk_at_file_offset(position) {
+            Some((i, offset)) => println!("${:X} -> hunk {} +${:X}, Ghidra {:08X}, dump {:08X}", position, i, offset,
+                                          ghidra[i] + offset, loaded[i] + offset),
+            None => println!("${:X} -> not inside the content of a hunk", position)
+        }
+    }
+}
+
+/// print the content of a UAE savestate and optionally write its RAM as memory dumps
+fn savestate(args: &ArgMatches) {
+    let state = SaveState::from_file(Path::new(args.value_of("file").unwrap())).expect("could not load savestate");
+    println!("{}: {}", state.emulator, state.description);
+    let chunks: Vec<String> = state.chunks.iter().map(|(name, size)| format!("{}({})", name.trim_end(), size)).collect();
+    println!("chunks: {}", chunks.join(" "));
+
+    if let Some((model, cpu)) = &state.cpu {
+        println!("\nCPU {}", model);
+        println!("{}", cpu.to_string());
+    }
+
+    println!("\nMemory\tFirst\tLast\tSize");
+    for (name, address, data) in &state.ram {
+        println!("{}\t{:08X}\t{:08X}\t{}K", name, address, address + data.len() as u32 - 1, data.len() / 1024);
+    }
+    for rom in &state.roms {
+        println!("ROM\t{:08X}\t{:08X}\t{}K\t{}.{} CRC32 {:08X} {} {}{}", rom.start, rom.start + rom.size - 1,
+                 rom.size / 1024, rom.version >> 16, rom.version & 0xFFFF, rom.crc32, rom.name, rom.path,
+                 if rom.data.is_some() { " (saved)" } else { "" });
+    }
+
+    if let Some((_, registers)) = &state.custom {
+        println!("\nRegister\tValue\tDecoded");
+        for (i, value) in registers.iter().enumerate().filter(|(_, v)| **v != 0) {
+            let address = hardware::CUSTOM_BASE + 2 * i as u32;
+            if let Some(name) = hardware::register_name(address) {
+                println!("{}\t{:04X}\t{}", name, value, hardware::decode(address, *value as u32));
+            }
+        }
+    }
+
+    if let Some(dir) = args.value_of("write") {
+        fs::create_dir_all(dir).expect("could not create directory");
+        for (_, address, data) in &state.ram {
+            fs::write(Path::new(dir).join(format!("{:08X}", address)), data).expect("could not write memory dump");
+        }
+        println!("\n{} memory dumps written to {}", state.ram.len(), dir);
+    }
+}
+
+/// print the fields of a structure at an address in one or more memory dumps, side by side
+fn mem_view(args: &ArgMatches) {
+    let dirs: Vec<&str> = std::iter::once(args.value_of("dir").unwrap())
+        .chain(args.values_of("dirs").unwrap_or_default()).collect();
+    let mems: Vec<MemDump> = dirs.iter()
+        .map(|dir| MemDump::from_dir(dir.to_string()).expect("could not load memory dump")).collect();
+    let structs = match args.value_of("structs") {
+        Some(file) => File::open(file).ok(),
+        None => FormatHelper::file_in_dir_or_parent(args, "structs")
+    }.map(Structs::from_file).expect("could not open structs file (see help-fs)");
+    let address = u32::from_str_radix(args.value_of("address").unwrap(), 16).expect("invalid address");
+
+    let mut queue = vec![(args.value_of("struct").unwrap().to_string(), address)];
+    let mut seen = BTreeSet::new();
+    while let Some((name, address)) = queue.pop() {
+        if !seen.insert((name.clone(), address)) {
+            continue;
+        }
+        let def = match structs.structs.get(&name) {
+            Some(def) => def,
+            None => {
+                println!("unknown structure {}", name);
+                continue;
+            }
+        };
+        println!("{} at {:08X}, size ${:X}", name, address, def.size);
+        println!("Offset\tAddress\tType\tField\t{}", dirs.join("\t"));
+        let mut follow = Vec::new();
+        for (offset, field, kind) in structs.flatten(&name) {
+            let values: Vec<String> = mems.iter().map(|mem| kind.read(mem, address + offset)).collect();
+            let differs = values.iter().any(|v| *v != values[0]);
+            println!("+{:04X}\t{:08X}\t{}\t{}\t{}{}", offset, address + offset, kind, field, values.join("\t"),
+                     if differs { "\t*" } else { "" });
+            if let FieldType::Pointer(Some(target)) = &kind {
+                // only follow pointers that are the same in all dumps
+                let pointers: Vec<Option<u32>> = mems.iter().map(|mem| mem.pointer_at(address + offset)).collect();
+                if let Some(pointer) = pointers[0].filter(|_| pointers.iter().all(|p| *p == pointers[0])) {
+                    follow.push((target.clone(), pointer));
+                }
+            }
+        }
+        println!();
+        if args.is_present("follow") {
+            queue.extend(follow.into_iter().rev());
+        }
+    }
+}
+
 /// search Dumps for a register change to a specific value
 /// multiple dumps (with one value each) can be specified, in which case only results that make the
 /// change at the same program counter in each dump are printed
@@ -195,7 +599,8 @@ fn search_value(args: &ArgMatches) {
     }
 
     for dump in dumps {
-        results = Some(dump.search_for_register_change(*values.get(i).unwrap(), size, results));
+        let filter = dump.step_filter(&fmt);
+        results = Some(dump.search_for_register_change(*values.get(i).unwrap(), size, results, &filter));
         i += 1;
     }
     for (k, v) in results.unwrap_or_default() {
@@ -203,24 +608,33 @@ fn search_value(args: &ArgMatches) {
     }
 }
 
+/// print comparisons involving a value and the outcome of the following branch
+fn comparisons(args: &ArgMatches) {
+    let path = args.value_of("dir").unwrap();
+    let val: u32 = args.value_of("val").unwrap().parse().expect("invalid value");
+    let fmt = FormatHelper::simple(true).finalize(args);
+
+    Dump::from_dir(path.to_string()).expect("could not load dump").comparisons(val, fmt);
+}
+
 fn map_data_to_mem(args: &ArgMatches) {
     let dump_dir = args.value_of("dir").unwrap();
     let data_dir = args.value_of("data-dir").unwrap();
 
-    let offset = FormatHelper::get_offset(&args);
+    let segments = FormatHelper::get_segments(args);
     let md = MemDump::from_dir(dump_dir.to_string()).expect("could not load memory");
-    md.map_data(data_dir.to_string(), offset).unwrap();
+    md.map_data(data_dir.to_string(), &segments).unwrap();
 }
 
 fn in_out_state(args: &ArgMatches) {
     let path = args.value_of("dir").unwrap();
     let dump = Dump::from_dir(path.to_string()).expect("could not load dump");
-    let offset = FormatHelper::get_offset(args);
+    let segments = FormatHelper::get_segments(args);
     // TODO use FormatHelper
-    let start = u32::from_str_radix(args.value_of("pc_start").unwrap(), 16)
-        .expect("could not parse start") + offset;
-    let end = u32::from_str_radix(args.value_of("pc_end").unwrap(), 16)
-        .expect("could not parse end") + offset;
+    let start = segments.to_dump(u32::from_str_radix(args.value_of("pc_start").unwrap(), 16)
+        .expect("could not parse start"));
+    let end = segments.to_dump(u32::from_str_radix(args.value_of("pc_end").unwrap(), 16)
+        .expect("could not parse end"));
     dump.in_out_state(start, end);
 }
 
@@ -235,82 +649,258 @@ fn print_mem_commands(args: &ArgMatches) {
 }
 
 /// check sets of memory dumps for bytes that differ between sets, but not inside them
-fn mem_set_diff(args: &ArgMatches) { // TODO improve error messages
-    let entries = fs::read_dir(args.value_of("set_dir").unwrap())
-        .expect("could not open dir");
-    let mut memdump_map: HashMap<String, Vec<MemDump>> = HashMap::new();
-    let offset = FormatHelper::get_offset(args);
+fn mem_set_diff(args: &ArgMatches) {
+    let set_dir = args.value_of("set_dir").unwrap();
+    let memdump_map = load_memory_sets(set_dir);
+    let segments = FormatHelper::get_segments(args);
 
-    // load memdumps and group them by the part of filename before '_'
-    for entry in entries {
-        let entry = entry.expect("something wrong with entry");
-        let path = entry.path();
-        let file_name = entry.file_name();
-        let name_parts: Vec<&str> = file_name.to_str().unwrap_or_default().split('_').collect();
-
-        if name_parts.len() == 2 {
-            let key = name_parts[0];
-            let mem = MemDump::from_dir(path.to_str().expect("something wrong with path").to_string()).expect("could not load mem dump");
-            let val = memdump_map.get_mut(key);
-            match val {
-                Some(e) => {
-                    e.push(mem);
-                }
-                None => {
-                    let mut vec: Vec<MemDump> = Vec::new();
-                    vec.push(mem);
-                    memdump_map.insert(key.to_owned(), vec);
-                }
-            }
-        }
+    if let Some(values) = args.values_of("value") {
+        known_value_search(args, &memdump_map, values.collect(), &segments);
+        return;
+    }
+    if memdump_map.len() < 2 {
+        eprintln!("found {} set(s) in {}, at least two are needed to find differences (see help-fs)",
+                  memdump_map.len(), set_dir);
+        return;
     }
 
-    // no need to have a map anymore - transfer to a Vec
-    let mut memdump_vec: Vec<Vec<MemDump>> = Vec::new();
-    for (_, val) in memdump_map.drain() {
-        memdump_vec.push(val);
+    // only compare memory that is contained in all dumps
+    let all: Vec<(&String, &MemDump)> = memdump_map.values().flatten().map(|(name, mem)| (name, mem)).collect();
+    let ranges = MemDump::common_ranges(&all.iter().map(|(_, mem)| *mem).collect::<Vec<_>>());
+    if ranges.is_empty() {
+        eprintln!("the memory dumps have no address range in common");
+        return;
+    }
+    let common: u32 = ranges.iter().map(|(from, to)| to - from).sum();
+    let ranges_text: Vec<String> = ranges.iter().map(|(from, to)| format!("{:08X}-{:08X}", from, to - 1)).collect();
+    eprintln!("comparing {}", ranges_text.join(", "));
+    for (name, mem) in &all {
+        let size: u32 = mem.ranges().iter().map(|(from, to)| to - from).sum();
+        if size > common {
+            eprintln!("{}: ignoring {} bytes outside of the ranges common to all dumps", name, size - common);
+        }
     }
 
-    // look up offsets that are different between each set
-    // only the first memdump per set is checked, as the interesting parts are identical in each
-    // memdump of a set and the false offsets will be filtered out later
-    let mut results: Option<BTreeSet<u32>> = None; // we use a sorted set, to get a sorted output
-    for i in 0..memdump_vec.len() - 1 {
-        // the first diff will check all memory, while subsequent diffs only need to check the
-        // offsets in results
-        for j in i + 1..memdump_vec.len() {
-            results = Some(memdump_vec[i][0].diff_only(&memdump_vec[j][0], results, false));
+    // keep bytes that differ between the first dumps of each pair of sets, as the interesting
+    // parts are identical in each memdump of a set
+    let sets: Vec<&Vec<(String, MemDump)>> = memdump_map.values().collect();
+    let mut results = AddressSet::full(&ranges);
+    for i in 0..sets.len() - 1 {
+        for j in i + 1..sets.len() {
+            results.retain_compared(&sets[i][0].1, &sets[j][0].1, false);
         }
     }
 
     // filter out bytes that change inside a set
-    for vec in memdump_vec {
-        for i in 0..vec.len() - 1 {
-            for j in i + 1..vec.len() {
-                results = Some(vec[i].diff_only(&vec[j], results, true));
-            }
+    for set in &sets {
+        for (_, mem) in &set[1..] {
+            results.retain_compared(&set[0].1, mem, true);
         }
     }
 
+    if results.is_empty() {
+        eprintln!("no bytes differ between all sets while being equal within each set");
+        return;
+    }
+    eprintln!("{} bytes found", results.len());
+
     // output
-    if offset == 0 {
-        for r in results.expect("No Result") {
-            println!("{:08X}", r);
+    let mut out: Box<dyn Write> = match args.value_of("out") {
+        Some(file) => Box::new(BufWriter::new(File::create(file).expect("could not create output file"))),
+        None => Box::new(std::io::stdout())
+    };
+    if args.value_of("format") == Some("list") {
+        for r in results.iter() {
+            if segments.is_identity() {
+                writeln!(out, "{:08X}", r)
+            } else {
+                writeln!(out, "{:08X}  {:08X}", segments.translate_or_wrap(r), r)
+            }.expect("writing failed");
         }
-    } else {
-        for r in results.expect("No Result") {
-            println!("{:08X}  {:08X}", r.wrapping_sub(offset), r);
+        return;
+    }
+
+    // contiguous bytes grouped, with the values of the first dump of each set
+    let fmt = FormatHelper::simple(true).finalize(args);
+    let names: Vec<&str> = memdump_map.keys().map(|name| name.as_str()).collect();
+    let groups: Vec<DiffGroup> = memdiff::group(results.iter()).into_iter().map(|(first, last)| DiffGroup {
+        first,
+        last,
+        ghidra: if segments.is_identity() { None } else { Some(segments.translate_or_wrap(first)) },
+        label: fmt.label_for(first),
+        values: sets.iter().map(|set| (first..=last).map(|a| set[0].1.byte_at(a).unwrap_or_default()).collect()).collect(),
+    }).collect();
+    match args.value_of("format") {
+        Some("csv") => memdiff::write_csv(&groups, &names, &mut out),
+        Some("json") => memdiff::write_json(&groups, &names, &mut out),
+        _ => memdiff::write_table(&groups, &names, &mut out)
+    }.expect("writing failed");
+}
+
+/// incremental search for an unknown value: start a session, narrow its candidates down with
+/// each further memory dump, or show them
+fn mem_search(args: &ArgMatches) {
+    match args.subcommand() {
+        Some(("start", args)) => {
+            let dir = args.value_of("dir").unwrap();
+            let widths = match args.values_of("size") {
+                Some(sizes) => sizes.map(|bits| bits.parse::<u8>().unwrap() / 8).collect(),
+                None => vec![1, 2, 4]
+            };
+            // fail early, instead of with the first filter
+            MemDump::from_dir(dir.to_string()).expect("could not load memory dump");
+            let session = SearchSession::new(dir, widths, args.is_present("unaligned"));
+            let path = args.value_of("session").unwrap();
+            session.write(path).expect("could not write session");
+            println!("session {} started with {}", path, dir);
+        }
+        Some(("next", args)) => {
+            let path = args.value_of("session").unwrap();
+            let mut session = SearchSession::from_file(path).expect("could not load session");
+            let relation = Relation::parse(args.value_of("relation").unwrap(), args.value_of("amount"))
+                .unwrap_or_else(|e| panic!("{}", e));
+            let dir = args.value_of("dir").unwrap();
+            let mem = MemDump::from_dir(dir.to_string()).expect("could not load memory dump");
+            session.filter(dir, &mem, relation);
+            session.write(path).expect("could not write session");
+            print_candidates(&session, args.value_of("limit").map_or(50, |l| l.parse().expect("invalid limit")));
+        }
+        Some(("show", args)) => {
+            let session = SearchSession::from_file(args.value_of("session").unwrap()).expect("could not load session");
+            for (i, (dir, relation)) in session.snapshots.iter().enumerate() {
+                match relation {
+                    Some(relation) => println!("{}\t{}\t{}", i, dir, relation),
+                    None => println!("{}\t{}\tstart", i, dir)
+                }
+            }
+            print_candidates(&session, args.value_of("limit").map_or(usize::MAX, |l| l.parse().expect("invalid limit")));
+        }
+        _ => println!("Unknown")
+    }
+}
+
+/// print up to limit candidates of a memory search session with the history of their values
+fn print_candidates(session: &SearchSession, limit: usize) {
+    let count = match session.count() {
+        Some(count) => count,
+        None => {
+            println!("all values are candidates, add the next memory dump");
+            return;
+        }
+    };
+    println!("{} candidates", count);
+    if count == 0 {
+        return;
+    }
+    let candidates = session.first(limit);
+    let histories = session.histories(&candidates).expect("could not load memory dump of session");
+    println!("Address\tSize\tValues");
+    for ((address, width), history) in candidates.iter().zip(histories) {
+        let values: Vec<String> = history.iter().map(|v| match v {
+            Some(v) => format!("{:0width$X}", v, width = 2 * *width as usize),
+            None => "??".to_string()
+        }).collect();
+        println!("{:08X}\t{}\t{}", address, 8 * width, values.join(" "));
+    }
+    if count > limit {
+        println!("... {} more", count - limit);
+    }
+}
+
+/// load the memory dumps in the directories of set_dir, grouped by the part of their name before
+/// '_', with the name of their directory
+fn load_memory_sets(set_dir: &str) -> BTreeMap<String, Vec<(String, MemDump)>> {
+    let entries = fs::read_dir(set_dir).unwrap_or_else(|e| panic!("could not open {}: {}", set_dir, e));
+    let mut memdump_map: BTreeMap<String, Vec<(String, MemDump)>> = BTreeMap::new();
+    for entry in entries {
+        let entry = entry.expect("something wrong with entry");
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let name_parts: Vec<&str> = file_name.split('_').collect();
+
+        if name_parts.len() == 2 && path.is_dir() {
+            let mem = MemDump::from_dir(path.to_string_lossy().to_string())
+                .unwrap_or_else(|e| panic!("could not load memory dump {}: {}", path.display(), e));
+            memdump_map.entry(name_parts[0].to_owned()).or_default().push((file_name.clone(), mem));
+        }
+    }
+    // sorted by name, for a stable choice of the first dump of each set
+    memdump_map.values_mut().for_each(|set| set.sort_by(|a, b| a.0.cmp(&b.0)));
+    memdump_map
+}
+
+/// print addresses where all dumps of each set hold the value given for the set (set=value)
+fn known_value_search(args: &ArgMatches, memdump_map: &BTreeMap<String, Vec<(String, MemDump)>>, values: Vec<&str>,
+                      segments: &SegmentMap) {
+    let mut sets: Vec<(Vec<&MemDump>, i64)> = Vec::new();
+    let mut given = BTreeSet::new();
+    for value in values {
+        let (set, value) = value.split_once('=').expect("values must be given as set=value");
+        let dumps = memdump_map.get(set).unwrap_or_else(|| panic!("no memory dumps found for set {}", set));
+        sets.push((dumps.iter().map(|(_, mem)| mem).collect(), value.parse().expect("invalid value (decimal)")));
+        given.insert(set);
+    }
+    for set in memdump_map.keys().filter(|set| !given.contains(set.as_str())) {
+        eprintln!("ignoring set {}, no value given", set);
+    }
+    let encodings: Vec<Encoding> = match args.values_of("encoding") {
+        Some(names) => names.flat_map(Encoding::from_arg).collect(),
+        None => Encoding::all()
+    };
+
+    for (address, encoding) in memsearch::find_known_values(&sets, &encodings, args.is_present("unaligned")) {
+        if segments.is_identity() {
+            println!("{:08X}  {}", address, encoding);
+        } else {
+            println!("{:08X}  {:08X}  {}", segments.translate_or_wrap(address), address, encoding);
         }
     }
 }
 
+/// print instruction patterns for Ghidra's search and, if the executable is given, verify them
+/// against it
 fn print_ghidra_search_pattern(args: &ArgMatches) {
     let path = args.value_of("dir").unwrap();
     let pc = u32::from_str_radix(args.value_of("pc").unwrap(), 16).unwrap();
     let num_after = usize::from_str_radix(
         args.value_of("count").unwrap_or("30"), 10).unwrap();
-    Dump::from_dir(path.to_string()).expect("could not load dump")
-        .ghidra_search(pc, num_after).expect("generating search pattern failed");
+    let fmt = FormatHelper::simple(true).finalize(args);
+    let exe = args.value_of("exe")
+        .map(|exe| HunkFile::from_file(Path::new(exe)).expect("could not load executable"));
+    let base = u32::from_str_radix(args.value_of("base").unwrap_or("21F000"), 16).expect("invalid base address");
+    let starts: Vec<u32> = exe.iter().flat_map(|exe| exe.addresses(base)).map(|a| fmt.segments.to_dump(a)).collect();
+    let dump = Dump::from_dir(path.to_string()).expect("could not load dump");
+    let relocs = exe.as_ref().map(|exe| (exe, starts.as_slice()));
+    let mut chunks = dump.ghidra_search(pc, num_after, &fmt, relocs).expect("generating search pattern failed");
+    for chunk in &chunks {
+        chunk.print(0..chunk.instructions.len());
+    }
+    let exe = match &exe {
+        Some(exe) => exe,
+        None => return
+    };
+
+    // verify against the executable, extending the pattern if it isn't unique
+    let matches: Vec<String> = pattern::count_matches(&chunks, exe).iter().map(|m| m.to_string()).collect();
+    println!("\n# matches per chunk in the executable: {}", matches.join(", "));
+    let mut count = num_after;
+    let mut unique = pattern::find_unique(&chunks, exe);
+    while unique.is_none() && count < num_after * pattern::MAX_EXTENSION {
+        count *= 2;
+        chunks = dump.ghidra_search(pc, count, &fmt, relocs).expect("generating search pattern failed");
+        unique = pattern::find_unique(&chunks, exe);
+    }
+    match unique {
+        Some((c, range, hunk, offset)) => {
+            let ghidra = exe.addresses(base)[hunk] + offset;
+            let first = chunks[c].instructions[range.start].0;
+            println!("# unique pattern ({} instructions collected), found in hunk {} +${:X}, file offset ${:X}, \
+                      Ghidra {:08X}, offset {:08X}", count, hunk, offset, exe.hunks[hunk].file_offset + offset,
+                     ghidra, first.wrapping_sub(ghidra));
+            chunks[c].print(range);
+        }
+        None => println!("# no unique pattern found within {} instructions", count)
+    }
 }
 
 fn print_starting_pcs(args: &ArgMatches) {
@@ -0,0 +1,173 @@
+/*
+    Copyright (C) 2020 Arkadiusz Guzinski <kermit@ag.de1.cc>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::io::{self, Write};
+
+/// bytes of longer groups shown in the table, the CSV and JSON contain all
+const TABLE_BYTES: usize = 16;
+
+/// a run of consecutive bytes found by memset-diff, with the values of each set
+pub struct DiffGroup {
+    pub first: u32,
+    pub last: u32,
+    /// address in Ghidra, if there is an offset or segments file
+    pub ghidra: Option<u32>,
+    /// Ghidra label or hunk segment the group starts in
+    pub label: Option<String>,
+    /// content in the first dump of each set
+    pub values: Vec<Vec<u8>>,
+}
+
+impl DiffGroup {
+    pub fn size(&self) -> u32 {
+        self.last - self.first + 1
+    }
+
+    /// the value as byte, word or long, if the group has one of these sizes
+    fn number(value: &[u8]) -> Option<u32> {
+        match value.len() {
+            1 | 2 | 4 => Some(value.iter().fold(0, |v, b| (v << 8) | *b as u32)),
+            _ => None
+        }
+    }
+
+    fn hex(value: &[u8]) -> String {
+        value.iter().map(|b| format!("{:02X}", b)).collect()
+    }
+
+    /// value as shown in the table: hex and decimal for bytes, words and longs. Other sizes are
+    /// shown as hex and the decimals of their longs, or words if the size isn't a multiple of 4,
+    /// both shortened.
+    fn display(value: &[u8]) -> String {
+        if let Some(n) = DiffGroup::number(value) {
+            return format!("{} ({})", DiffGroup::hex(value), n);
+        }
+        let shown = &value[..value.len().min(TABLE_BYTES)];
+        let more = if shown.len() < value.len() { ".." } else { "" };
+        let width = if value.len().is_multiple_of(4) { 4 } else { 2 };
+        let decimals: Vec<String> = shown.chunks(width)
+            .map(|part| part.iter().fold(0u32, |v, b| (v << 8) | *b as u32).to_string())
+            .collect();
+        format!("{}{} ({}{})", DiffGroup::hex(shown), more, decimals.join(" "), more)
+    }
+}
+
+/// group ascending addresses into runs of consecutive addresses (first, last)
+pub fn group(addresses: impl Iterator<Item = u32>) -> Vec<(u32, u32)> {
+    let mut groups: Vec<(u32, u32)> = Vec::new();
+    for address in addresses {
+        match groups.last_mut() {
+            Some((_, last)) if *last + 1 == address => *last = address,
+            _ => groups.push((address, address))
+        }
+    }
+    groups
+}
+
+/// write groups as tab separated table, with the values of the sets side by side
+pub fn write_table(groups: &[DiffGroup], sets: &[&str], out: &mut dyn Write) -> io::Result<()> {
+    let with_ghidra = groups.iter().any(|g| g.ghidra.is_some());
+    writeln!(out, "First\tLast\tSize\t{}Label\t{}", if with_ghidra { "Ghidra\t" } else { "" }, sets.join("\t"))?;
+    for g in groups {
+        let values: Vec<String> = g.values.iter().map(|v| DiffGroup::display(v)).collect();
+        let ghidra = match g.ghidra {
+            Some(address) => format!("{:08X}\t", address),
+            None if with_ghidra => "-\t".to_string(),
+            None => String::new()
+        };
+        writeln!(out, "{:08X}\t{:08X}\t{}\t{}{}\t{}", g.first, g.last, g.size(), ghidra,
+                 g.label.as_deref().unwrap_or("-"), values.join("\t"))?;
+    }
+    Ok(())
+}
+
+/// write groups as CSV, with a hex and a decimal column per set
+pub fn write_csv(groups: &[DiffGroup], sets: &[&str], out: &mut dyn Write) -> io::Result<()> {
+    let columns: Vec<String> = sets.iter().map(|s| format!("{0} hex,{0} dec", escape_csv(s))).collect();
+    writeln!(out, "first,last,size,ghidra,label,{}", columns.join(","))?;
+    for g in groups {
+        let values: Vec<String> = g.values.iter().map(|v| format!("{},{}", DiffGroup::hex(v),
+            DiffGroup::number(v).map(|n| n.to_string()).unwrap_or_default())).collect();
+        writeln!(out, "{:08X},{:08X},{},{},{},{}", g.first, g.last, g.size(),
+                 g.ghidra.map(|a| format!("{:08X}", a)).unwrap_or_default(),
+                 escape_csv(g.label.as_deref().unwrap_or_default()), values.join(","))?;
+    }
+    Ok(())
+}
+
+/// write groups as JSON array of objects, values by set name
+pub fn write_json(groups: &[DiffGroup], sets: &[&str], out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "[")?;
+    for (i, g) in groups.iter().enumerate() {
+        let values: Vec<String> = sets.iter().zip(&g.values).map(|(set, v)| match DiffGroup::number(v) {
+            Some(n) => format!("\"{}\": {{\"hex\": \"{}\", \"dec\": {}}}", escape_json(set), DiffGroup::hex(v), n),
+            None => format!("\"{}\": {{\"hex\": \"{}\"}}", escape_json(set), DiffGroup::hex(v))
+        }).collect();
+        writeln!(out, "  {{\"first\": \"{:08X}\", \"last\": \"{:08X}\", \"size\": {}, \"ghidra\": {}, \"label\": {}, \"values\": {{{}}}}}{}",
+                 g.first, g.last, g.size(),
+                 g.ghidra.map_or("null".to_string(), |a| format!("\"{:08X}\"", a)),
+                 g.label.as_ref().map_or("null".to_string(), |l| format!("\"{}\"", escape_json(l))),
+                 values.join(", "), if i + 1 < groups.len() { "," } else { "" })?;
+    }
+    writeln!(out, "]")
+}
+
+/// quote CSV field if needed
+fn escape_csv(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// escape string for use in JSON
+fn escape_json(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04X}", c as u32)),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_escapes() {
+        assert_eq!(escape_json("a\"b\\c"), "a\\\"b\\\\c");
+        assert_eq!(escape_json("line\r\n\tend"), "line\\r\\n\\tend");
+        assert_eq!(escape_json("\u{0}\u{1F} ü"), "\\u0000\\u001F ü");
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(DiffGroup::display(&[0x01, 0x00]), "0100 (256)");
+        assert_eq!(DiffGroup::display(&[0, 0, 0, 1, 0, 0, 0, 2]), "0000000100000002 (1 2)");
+        assert_eq!(DiffGroup::display(&[1, 2, 3]), "010203 (258 3)");
+        let long: Vec<u8> = (0..20).collect();
+        assert_eq!(DiffGroup::display(&long), "000102030405060708090A0B0C0D0E0F.. (66051 67438087 134810123 202182159..)");
+    }
+}
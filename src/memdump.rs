@@ -20,9 +20,10 @@ use std::io::{BufReader, BufRead, Read};
 use std::cmp::{min, max};
 use walkdir::WalkDir;
 use twoway;
-use std::collections::BTreeSet;
-use std::process::exit;
 use std::ops::Index;
+use crate::segments::SegmentMap;
+use crate::savestate::SaveState;
+use std::path::Path;
 
 pub struct MemDump {
     /// structure for a partial memory dump
@@ -60,10 +61,50 @@ impl MemDump {
             return Ok(mem_dump);
         }
 
+        // UAE savestate, there must not be several to choose from
+        let mut states = Vec::new();
+        for dir_entry in fs::read_dir(&path)? {
+            let file_path = dir_entry?.path();
+            if file_path.extension().and_then(|e| e.to_str()) == Some("uss") {
+                states.push(file_path);
+            }
+        }
+        match states.as_slice() {
+            [state] => return MemDump::from_savestate(state),
+            [] => {}
+            _ => {
+                let mut names: Vec<String> = states.iter()
+                    .map(|p| p.file_name().unwrap_or_default().to_string_lossy().to_string()).collect();
+                names.sort();
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                    format!("several savestates in {}, keep only one: {}", path, names.join(", "))));
+            }
+        }
+
         let file = File::open(path.to_owned() + "/mem")?;
         MemDump::load_from_text(file)
     }
 
+    /// load the RAM (and ROMs, if saved with it) of a UAE savestate
+    pub fn from_savestate(path: &Path) -> std::io::Result<MemDump> {
+        let state = SaveState::from_file(path)?;
+        let mut mem_dump = MemDump::new();
+        for (_, address, data) in state.ram {
+            mem_dump.add_part(address, data);
+        }
+        for rom in state.roms {
+            if let Some(data) = rom.data {
+                mem_dump.add_part(rom.start, data);
+            }
+        }
+        Ok(mem_dump)
+    }
+
+    /// add data starting at address from, e.g. a ROM image
+    pub fn add_part(&mut self, from: u32, data: Vec<u8>) {
+        self.parts.push(MemPart { from, to: data.len() as u32 + from, data });
+    }
+
     /// load MemPart from a file in directory path. filename is the starting address in hex (no 0x)
     fn load_from_bin(&mut self, mut file: File, start: u32) -> std::io::Result<()> {
         // let size = file.
@@ -123,7 +164,101 @@ impl MemDump {
         format!("{:08X}: ??", addr)
     }
 
-    pub fn map_data(&self, path: String, offset: u32) -> std::io::Result<()> {
+    /// start address and content of each consecutive part of the dump
+    pub fn parts(&self) -> impl Iterator<Item = (u32, &[u8])> {
+        self.parts.iter().map(|part| (part.from, part.data.as_slice()))
+    }
+
+    /// is addr inside the dump?
+    pub fn contains(&self, addr: u32) -> bool {
+        self.bytes_at(addr, 1).is_some()
+    }
+
+    /// returns the byte at addr, if it is inside the dump
+    pub fn byte_at(&self, addr: u32) -> Option<u8> {
+        self.bytes_at(addr, 1).map(|b| b[0])
+    }
+
+    /// returns the signed byte at addr, if it is inside the dump
+    pub fn sbyte_at(&self, addr: u32) -> Option<i8> {
+        self.byte_at(addr).map(|b| b as i8)
+    }
+
+    /// returns the big endian word at addr, if it is inside the dump
+    pub fn word_at(&self, addr: u32) -> Option<u16> {
+        self.bytes_at(addr, 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    /// returns the big endian long at addr, if it is inside the dump
+    pub fn long_at(&self, addr: u32) -> Option<u32> {
+        self.bytes_at(addr, 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// returns the signed big endian word at addr, if it is inside the dump
+    pub fn sword_at(&self, addr: u32) -> Option<i16> {
+        self.word_at(addr).map(|w| w as i16)
+    }
+
+    /// returns the signed big endian long at addr, if it is inside the dump
+    pub fn slong_at(&self, addr: u32) -> Option<i32> {
+        self.long_at(addr).map(|l| l as i32)
+    }
+
+    /// returns the long at addr, if it is a pointer into the dump
+    pub fn pointer_at(&self, addr: u32) -> Option<u32> {
+        self.long_at(addr).filter(|target| self.contains(*target))
+    }
+
+    /// returns len bytes at addr as text, up to the first zero byte. Non printable characters are
+    /// replaced by '.'.
+    pub fn chars_at(&self, addr: u32, len: usize) -> Option<String> {
+        let bytes = self.bytes_at(addr, len)?;
+        Some(bytes.iter().take_while(|c| **c != 0)
+            .map(|c| if (0x20..0x7f).contains(c) { *c as char } else { '.' })
+            .collect())
+    }
+
+    /// returns the zero terminated string at addr, if it is inside the dump, printable and
+    /// at most max characters long
+    pub fn string_at(&self, addr: u32, max: usize) -> Option<String> {
+        let mut s = String::new();
+        for i in 0..=max as u32 {
+            match self.bytes_at(addr.wrapping_add(i), 1)?[0] {
+                0 => return Some(s),
+                c if (0x20..0x7f).contains(&c) => s.push(c as char),
+                _ => return None
+            }
+        }
+        None
+    }
+
+    /// addresses of all occurrences of pattern
+    pub fn find(&self, pattern: &[u8]) -> Vec<u32> {
+        let mut found = Vec::new();
+        for part in &self.parts {
+            let mut pos = 0;
+            while let Some(i) = twoway::find_bytes(&part.data[pos..], pattern) {
+                found.push(part.from + (pos + i) as u32);
+                pos += i + 1;
+            }
+        }
+        found
+    }
+
+    /// returns count bytes at addr, if they are all inside one part of the dump
+    pub fn bytes_at(&self, addr: u32, count: usize) -> Option<&[u8]> {
+        for part in &self.parts {
+            if addr >= part.from {
+                let from = (addr - part.from) as usize;
+                if let Some(bytes) = part.data.get(from..from + count) {
+                    return Some(bytes);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn map_data(&self, path: String, segments: &SegmentMap) -> std::io::Result<()> {
         println!("File\tIndex\tMem\tTranslated\tSize");
         for entry_opt in WalkDir::new(path) {
             let entry = entry_opt?;
@@ -139,7 +274,7 @@ impl MemDump {
                                               format!("{}\t{}\t",
                                                       dir.as_os_str().to_str().unwrap_or_default(),
                                                       name.as_os_str().to_str().unwrap_or_default()),
-                                              offset),
+                                              segments),
                         _ => println!("failed reading file {}", full_path.to_str().unwrap_or_default())
                     }
                 };
@@ -149,7 +284,7 @@ impl MemDump {
         Ok(())
     }
 
-    fn map_data_for(&self, mut file: File, pre: String, offset: u32) {
+    fn map_data_for(&self, mut file: File, pre: String, segments: &SegmentMap) {
         let mut data = Vec::new();
         if let Ok(_) = file.read_to_end(&mut data) {
             if !MemDump::check_entropy(&data) {
@@ -158,7 +293,6 @@ impl MemDump {
             for part in self.parts.iter() {
                 let mut last_pos = 0;
                 let start = part.from as usize;
-                let s_mod = (part.from - offset) as usize;
                 loop {
                     let slice = &part.data.as_slice()[last_pos..];
                     // println!("BLUB!!!! {}   {}", slice.len(), data.len());
@@ -169,7 +303,7 @@ impl MemDump {
                         }
                         println!("{}0x{:08X}\t0x{:08X}\t{}", &pre,
                                  start + pos,
-                                 s_mod + pos,
+                                 segments.translate_or_wrap((start + pos) as u32),
                                  data.len()
                         );
                         last_pos = pos + 1;
@@ -189,35 +323,28 @@ impl MemDump {
         size >= 8
     }
 
-    pub fn diff_only(&self, other: &MemDump, only: Option<BTreeSet<u32>>, invert: bool) -> BTreeSet<u32> {
-        if self.parts.len() != other.parts.len() {
-            exit(1);
-        }
-        let mut results: BTreeSet<u32> = BTreeSet::new();
-        match only {
-            Some(only) => {
-                for i in only.iter() {
-                    if (self[*i] == other[*i]) == invert {
-                        results.insert(*i);
-                    }
-                }
-            },
-            None => {
-                for i in 0..self.parts.len() {
-                    let l_part = &self.parts[i];
-                    let r_part = &other.parts[i];
-                    if l_part.from != r_part.from || l_part.to != r_part.to {
-                        exit(1);
-                    }
-                    for j in 0..l_part.data.len() {
-                        if (l_part.data[j] == r_part.data[j]) == invert {
-                            results.insert(j as u32 + l_part.from);
-                        }
-                    }
-                }
-            }
+    /// address ranges (first, last + 1) of the parts
+    pub fn ranges(&self) -> Vec<(u32, u32)> {
+        self.parts().map(|(from, data)| (from, from + data.len() as u32)).collect()
+    }
+
+    /// address ranges (first, last + 1) contained in all dumps, each inside a single part of every
+    /// dump, sorted
+    pub fn common_ranges(dumps: &[&MemDump]) -> Vec<(u32, u32)> {
+        let mut ranges = match dumps.first() {
+            Some(first) => first.ranges(),
+            None => return Vec::new()
+        };
+        for mem in &dumps[1..] {
+            let other = mem.ranges();
+            ranges = ranges.iter().flat_map(|(from, to)| other.iter().filter_map(move |(o_from, o_to)| {
+                let (first, end) = (max(*from, *o_from), min(*to, *o_to));
+                if first < end { Some((first, end)) } else { None }
+            })).collect();
         }
-        results
+        ranges.sort_unstable();
+        ranges.dedup();
+        ranges
     }
 
     /*    fn find_bytes(text: &[u8], pattern: &[u8]) -> Option<usize> {
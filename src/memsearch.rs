@@ -0,0 +1,320 @@
+/*
+    Copyright (C) 2020 Arkadiusz Guzinski <kermit@ag.de1.cc>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use serde::{Serialize, Deserialize};
+use crate::addressset::AddressSet;
+use crate::memdump::MemDump;
+
+/// how a known value is stored in memory
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Encoding {
+    /// big endian binary value of 1, 2 or 4 bytes
+    Binary(usize),
+    /// packed BCD (two decimal digits per byte) of 1 to 4 bytes
+    Bcd(usize),
+}
+
+impl Encoding {
+    /// encodings for the value of the command line option: 8, 16, 32 or bcd (all widths)
+    pub fn from_arg(arg: &str) -> Vec<Encoding> {
+        match arg {
+            "8" => vec![Encoding::Binary(1)],
+            "16" => vec![Encoding::Binary(2)],
+            "32" => vec![Encoding::Binary(4)],
+            "bcd" => (1..=4).map(Encoding::Bcd).collect(),
+            _ => Vec::new()
+        }
+    }
+
+    /// all encodings
+    pub fn all() -> Vec<Encoding> {
+        ["8", "16", "32", "bcd"].iter().flat_map(|arg| Encoding::from_arg(arg)).collect()
+    }
+
+    /// number of bytes
+    pub fn width(&self) -> usize {
+        match *self {
+            Encoding::Binary(width) | Encoding::Bcd(width) => width,
+        }
+    }
+
+    /// bytes of value in this encoding, None if it doesn't fit. Negative values are stored as
+    /// two's complement, they can't be encoded as BCD.
+    pub fn encode(&self, value: i64) -> Option<Vec<u8>> {
+        match *self {
+            Encoding::Binary(width) => {
+                let bits = 8 * width as u32;
+                if value >= 1 << bits || value < -(1 << (bits - 1)) {
+                    return None;
+                }
+                Some((value as u64).to_be_bytes()[8 - width..].to_vec())
+            }
+            Encoding::Bcd(width) => {
+                if value < 0 || value >= 100i64.pow(width as u32) {
+                    return None;
+                }
+                let mut bytes = vec![0u8; width];
+                let mut rest = value;
+                for byte in bytes.iter_mut().rev() {
+                    *byte = (((rest % 100 / 10) << 4) | (rest % 10)) as u8;
+                    rest /= 100;
+                }
+                Some(bytes)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Encoding::Binary(width) => write!(f, "{}bit", 8 * width),
+            Encoding::Bcd(width) => write!(f, "bcd{}", 8 * width),
+        }
+    }
+}
+
+/// addresses where every dump of each set holds the value of its set, for each encoding in which
+/// all values fit. sets are (dumps, value); the result is sorted by address. Values of more than one
+/// byte at odd addresses are only found if unaligned is set (not accessible on a 68000).
+pub fn find_known_values(sets: &[(Vec<&MemDump>, i64)], encodings: &[Encoding], unaligned: bool) -> Vec<(u32, Encoding)> {
+    let mut results = Vec::new();
+    for encoding in encodings {
+        let patterns: Option<Vec<Vec<u8>>> = sets.iter().map(|(_, value)| encoding.encode(*value)).collect();
+        let patterns = match patterns {
+            Some(patterns) if !sets.is_empty() && !sets[0].0.is_empty() => patterns,
+            _ => continue
+        };
+        // candidates are the occurrences in the first dump, which are checked in all the others
+        let aligned = |address: &u32| unaligned || encoding.width() == 1 || address.is_multiple_of(2);
+        for address in sets[0].0[0].find(&patterns[0]).into_iter().filter(aligned) {
+            let everywhere = sets.iter().zip(&patterns).all(|((dumps, _), pattern)|
+                dumps.iter().all(|mem| mem.bytes_at(address, pattern.len()) == Some(pattern.as_slice())));
+            if everywhere {
+                results.push((address, *encoding));
+            }
+        }
+    }
+    results.sort_by_key(|(address, _)| *address);
+    results
+}
+
+/// how the value of a candidate changed from one snapshot to the next
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Relation {
+    /// increased, by exactly the given amount if any
+    Increased(Option<u32>),
+    /// decreased, by exactly the given amount if any
+    Decreased(Option<u32>),
+    Unchanged,
+    Changed,
+    /// equal to the given value, regardless of the previous one
+    Equal(u32),
+}
+
+impl Relation {
+    /// parse relation and optional amount, as given on the command line
+    pub fn parse(name: &str, amount: Option<&str>) -> Result<Relation, String> {
+        let amount = match amount {
+            Some(a) => Some(a.parse::<u32>().map_err(|_| format!("invalid amount {} (decimal)", a))?),
+            None => None
+        };
+        match (name, amount) {
+            ("increased" | "inc", _) => Ok(Relation::Increased(amount)),
+            ("decreased" | "dec", _) => Ok(Relation::Decreased(amount)),
+            ("unchanged" | "same", None) => Ok(Relation::Unchanged),
+            ("changed", None) => Ok(Relation::Changed),
+            ("equal" | "eq", Some(value)) => Ok(Relation::Equal(value)),
+            ("equal" | "eq", None) => Err("equal needs a value".to_string()),
+            _ => Err(format!("invalid relation {}", name))
+        }
+    }
+
+    /// does the change from old to new (unsigned values) satisfy the relation?
+    pub fn holds(&self, old: u32, new: u32) -> bool {
+        match *self {
+            Relation::Increased(None) => new > old,
+            Relation::Increased(Some(amount)) => new > old && new - old == amount,
+            Relation::Decreased(None) => new < old,
+            Relation::Decreased(Some(amount)) => new < old && old - new == amount,
+            Relation::Unchanged => new == old,
+            Relation::Changed => new != old,
+            Relation::Equal(value) => new == value,
+        }
+    }
+}
+
+impl fmt::Display for Relation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Relation::Increased(None) => write!(f, "increased"),
+            Relation::Increased(Some(amount)) => write!(f, "increased by {}", amount),
+            Relation::Decreased(None) => write!(f, "decreased"),
+            Relation::Decreased(Some(amount)) => write!(f, "decreased by {}", amount),
+            Relation::Unchanged => write!(f, "unchanged"),
+            Relation::Changed => write!(f, "changed"),
+            Relation::Equal(value) => write!(f, "equal {}", value),
+        }
+    }
+}
+
+/// the values of one width that satisfied all relations so far
+#[derive(Serialize, Deserialize)]
+pub struct Candidates {
+    /// size of the values in bytes (1, 2 or 4)
+    pub width: u8,
+    pub addresses: AddressSet,
+    /// value in the latest snapshot, per address in ascending order
+    values: Vec<u32>,
+}
+
+impl Candidates {
+    /// keep only the addresses whose value in mem relates to the latest one as given
+    fn filter(&mut self, mem: &MemDump, relation: Relation) {
+        let width = self.width;
+        let mut old = self.values.iter();
+        let mut values = Vec::new();
+        self.addresses.retain(|address| match value_at(mem, address, width) {
+            Some(new) if relation.holds(*old.next().unwrap(), new) => {
+                values.push(new);
+                true
+            }
+            _ => false
+        });
+        self.values = values;
+    }
+}
+
+/// an incremental search for a value that is unknown, but whose changes are known: each snapshot
+/// of memory narrows the candidates down to those that changed as described
+#[derive(Serialize, Deserialize)]
+pub struct SearchSession {
+    /// directories of the snapshots and the relation to the previous snapshot (None for the first)
+    pub snapshots: Vec<(String, Option<Relation>)>,
+    /// sizes of the values searched for, in bytes
+    pub widths: Vec<u8>,
+    /// also search words and longs at odd addresses (not accessible on a 68000)
+    pub unaligned: bool,
+    /// candidates per width. None until the second snapshot: all values of the first snapshot
+    /// are candidates
+    pub candidates: Option<Vec<Candidates>>,
+}
+
+impl SearchSession {
+    /// start a session with the memory dump in dir
+    pub fn new(dir: &str, widths: Vec<u8>, unaligned: bool) -> SearchSession {
+        SearchSession { snapshots: vec![(canonical(dir), None)], widths, unaligned, candidates: None }
+    }
+
+    /// load session from file
+    pub fn from_file(path: &str) -> io::Result<SearchSession> {
+        bincode::deserialize_from(BufReader::new(File::open(path)?))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// write session to file
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        bincode::serialize_into(BufWriter::new(File::create(path)?), self)
+            .map_err(io::Error::other)
+    }
+
+    /// keep only the candidates whose value in mem (the snapshot in dir) relates to the previous
+    /// one as given
+    pub fn filter(&mut self, dir: &str, mem: &MemDump, relation: Relation) {
+        let candidates = match self.candidates.take() {
+            Some(mut candidates) => {
+                candidates.iter_mut().for_each(|c| c.filter(mem, relation));
+                candidates
+            }
+            None => self.first_candidates(mem, relation)
+        };
+        self.candidates = Some(candidates);
+        self.snapshots.push((canonical(dir), Some(relation)));
+    }
+
+    /// compare all values of the first snapshot with those in mem
+    fn first_candidates(&self, mem: &MemDump, relation: Relation) -> Vec<Candidates> {
+        let first = MemDump::from_dir(self.snapshots[0].0.clone()).expect("could not load first memory dump of session");
+        self.widths.iter().map(|width| {
+            // (first address, last address + 1) of all values that fit into a part
+            let ranges: Vec<(u32, u32)> = first.parts()
+                .filter(|(_, data)| data.len() >= *width as usize)
+                .map(|(from, data)| (from, from + (data.len() + 1 - *width as usize) as u32))
+                .collect();
+            let mut addresses = AddressSet::full(&ranges);
+            let mut values = Vec::new();
+            let aligned = self.unaligned || *width == 1;
+            addresses.retain(|address| {
+                if !aligned && !address.is_multiple_of(2) {
+                    return false;
+                }
+                match (value_at(&first, address, *width), value_at(mem, address, *width)) {
+                    (Some(old), Some(new)) if relation.holds(old, new) => {
+                        values.push(new);
+                        true
+                    }
+                    _ => false
+                }
+            });
+            Candidates { width: *width, addresses, values }
+        }).collect()
+    }
+
+    /// number of candidates, None until the second snapshot
+    pub fn count(&self) -> Option<usize> {
+        self.candidates.as_ref().map(|candidates| candidates.iter().map(|c| c.addresses.len()).sum())
+    }
+
+    /// the first limit candidates (address, width), by address
+    pub fn first(&self, limit: usize) -> Vec<(u32, u8)> {
+        let mut first: Vec<(u32, u8)> = self.candidates.iter().flatten()
+            .flat_map(|c| c.addresses.iter().take(limit).map(move |address| (address, c.width)))
+            .collect();
+        first.sort();
+        first.truncate(limit);
+        first
+    }
+
+    /// values of the candidates in each snapshot, loaded from the snapshot directories. Values
+    /// outside a dump are None.
+    pub fn histories(&self, candidates: &[(u32, u8)]) -> io::Result<Vec<Vec<Option<u32>>>> {
+        let mut histories = vec![Vec::new(); candidates.len()];
+        for (dir, _) in &self.snapshots {
+            let mem = MemDump::from_dir(dir.clone())?;
+            for (history, (address, width)) in histories.iter_mut().zip(candidates) {
+                history.push(value_at(&mem, *address, *width));
+            }
+        }
+        Ok(histories)
+    }
+}
+
+/// absolute path of dir, so that the session can be used from any directory
+fn canonical(dir: &str) -> String {
+    fs::canonicalize(dir).map_or_else(|_| dir.to_string(), |path| path.to_string_lossy().to_string())
+}
+
+/// unsigned big endian value of width bytes at address
+fn value_at(mem: &MemDump, address: u32, width: u8) -> Option<u32> {
+    match width {
+        1 => mem.byte_at(address).map(|v| v as u32),
+        2 => mem.word_at(address).map(|v| v as u32),
+        _ => mem.long_at(address),
+    }
+}
@@ -0,0 +1,249 @@
+/*
+    Copyright (C) 2020 Arkadiusz Guzinski <kermit@ag.de1.cc>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use crate::cpustep::CpuStep;
+use std::fmt;
+
+/// an operand of an instruction, as disassembled by fs-uae
+#[derive(Debug, PartialEq)]
+pub enum Operand {
+    /// Dn
+    DataReg(usize),
+    /// An
+    AddrReg(usize),
+    /// #$xxxx
+    Immediate(u32),
+    /// anything accessing memory. address is set, if fs-uae printed it (" == $xxxxxxxx") or it
+    /// could be read from the text directly (absolute addressing)
+    Memory { text: String, address: Option<u32> },
+    /// anything else (register lists, status register, ...)
+    Other(String),
+}
+
+/// size of an operation, as given by the suffix of the mnemonic
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Size { Byte, Word, Long, Unsized }
+
+impl Size {
+    /// parse suffix (without the '.')
+    pub fn from_suffix(suffix: &str) -> Size {
+        match suffix.trim() {
+            "B" => Size::Byte,
+            "W" => Size::Word,
+            "L" => Size::Long,
+            _ => Size::Unsized
+        }
+    }
+
+    /// number of bytes accessed. Unsized operations are treated as long.
+    pub fn bytes(&self) -> u32 {
+        match self {
+            Size::Byte => 1,
+            Size::Word => 2,
+            _ => 4
+        }
+    }
+
+    /// bit mask for values of this size. Unsized operations are treated as long.
+    pub fn mask(&self) -> u32 {
+        match self {
+            Size::Byte => 0x000000FF,
+            Size::Word => 0x0000FFFF,
+            _ => 0xFFFFFFFF
+        }
+    }
+}
+
+impl Operand {
+    /// parse a single operand
+    pub fn parse(text: &str) -> Operand {
+        let t = text.trim();
+        // fs-uae appends the effective address for most memory accesses
+        let (ea, address) = match t.find(" == $") {
+            Some(i) => (t[..i].trim(), u32::from_str_radix(
+                t[i + 5..].split_whitespace().next().unwrap_or_default(), 16).ok()),
+            None => (t, None)
+        };
+        let bytes = ea.as_bytes();
+        if bytes.len() == 2 && bytes[1].is_ascii_digit() && bytes[1] <= b'7' {
+            match bytes[0] {
+                b'D' => return Operand::DataReg((bytes[1] - b'0') as usize),
+                b'A' => return Operand::AddrReg((bytes[1] - b'0') as usize),
+                _ => {}
+            }
+        }
+        if ea == "SP" {
+            return Operand::AddrReg(7);
+        }
+        if let Some(imm) = ea.strip_prefix("#$") {
+            if let Ok(val) = u32::from_str_radix(imm, 16) {
+                return Operand::Immediate(val);
+            }
+        } else if let Some(imm) = ea.strip_prefix('#') {
+            if let Ok(val) = imm.parse::<i32>() {
+                return Operand::Immediate(val as u32);
+            }
+        }
+        if ea.starts_with('(') || ea.starts_with("-(") {
+            return Operand::Memory { text: ea.to_string(), address };
+        }
+        if let Some(abs) = ea.strip_prefix('$') {
+            let abs = abs.trim_end_matches(".W").trim_end_matches(".L");
+            if let Ok(val) = u32::from_str_radix(abs, 16) {
+                return Operand::Memory { text: ea.to_string(), address: Some(val) };
+            }
+        }
+        Operand::Other(ea.to_string())
+    }
+
+    /// split operand part of an instruction at top level commas and parse each operand
+    pub fn parse_list(text: &str) -> Vec<Operand> {
+        let mut result = Vec::new();
+        let mut level = 0;
+        let mut start = 0;
+        for (i, c) in text.char_indices() {
+            match c {
+                '(' => level += 1,
+                ')' => level -= 1,
+                ',' if level == 0 => {
+                    result.push(Operand::parse(&text[start..i]));
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        if !text[start..].trim().is_empty() {
+            result.push(Operand::parse(&text[start..]));
+        }
+        result
+    }
+
+    /// address register and displacement of a memory operand of the form (An, $xxxx),
+    /// (An, -$xxxx) or -$xxxx(An)
+    pub fn displacement(&self) -> Option<(usize, i32)> {
+        let text = match self {
+            Operand::Memory { text, .. } => text.replace(' ', ""),
+            _ => return None
+        };
+        let (reg, disp) = if let Some(inner) = text.strip_prefix("(A").and_then(|t| t.strip_suffix(')')) {
+            inner.split_once(',')?
+        } else {
+            let (disp, reg) = text.strip_suffix(')')?.split_once("(A")?;
+            (reg, disp)
+        };
+        let reg: usize = reg.parse().ok().filter(|r| *r < 8)?;
+        let (negative, disp) = match disp.strip_prefix('-') {
+            Some(d) => (true, d),
+            None => (false, disp.trim_start_matches('+'))
+        };
+        let val = i32::from_str_radix(disp.strip_prefix('$')?, 16).ok()?;
+        Some((reg, if negative { -val } else { val }))
+    }
+
+    /// effective address of a memory operand at step (before the instruction is executed).
+    /// The address printed by fs-uae is preferred, otherwise it is computed from the registers
+    /// for (An), (An)+, -(An) and displacement modes.
+    pub fn address(&self, step: &CpuStep, size: Size) -> Option<u32> {
+        let text = match self {
+            Operand::Memory { address: Some(address), .. } => return Some(*address),
+            Operand::Memory { text, .. } => text.replace(' ', ""),
+            _ => return None
+        };
+        if let Some((reg, disp)) = self.displacement() {
+            return Some(step.address[reg].wrapping_add(disp as u32));
+        }
+        let register = |t: &str| t.strip_prefix("(A")?.strip_suffix(')')?.parse::<usize>().ok().filter(|r| *r < 8);
+        if let Some(reg) = text.strip_prefix('-').and_then(register) {
+            // the stack pointer is kept even for byte accesses
+            let bytes = if reg == 7 && size == Size::Byte { 2 } else { size.bytes() };
+            return Some(step.address[reg].wrapping_sub(bytes));
+        }
+        register(text.trim_end_matches('+')).map(|reg| step.address[reg])
+    }
+
+    /// value of this operand at step (before the instruction is executed), if it can be known
+    /// from registers alone. Memory is not resolved.
+    pub fn value(&self, step: &CpuStep, size: Size) -> Option<u32> {
+        match self {
+            Operand::DataReg(i) => Some(step.data[*i] & size.mask()),
+            Operand::AddrReg(i) => Some(step.address[*i] & size.mask()),
+            Operand::Immediate(v) => Some(*v & size.mask()),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::DataReg(i) => write!(f, "D{}", i),
+            Operand::AddrReg(i) => write!(f, "A{}", i),
+            Operand::Immediate(v) => write!(f, "#${:X}", v),
+            Operand::Memory { text, .. } => write!(f, "{}", text),
+            Operand::Other(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory(text: &str, address: Option<u32>) -> Operand {
+        Operand::Memory { text: text.to_string(), address }
+    }
+
+    #[test]
+    fn parse_registers_and_immediates() {
+        assert_eq!(Operand::parse("D3"), Operand::DataReg(3));
+        assert_eq!(Operand::parse(" A6 "), Operand::AddrReg(6));
+        assert_eq!(Operand::parse("SP"), Operand::AddrReg(7));
+        assert_eq!(Operand::parse("#$0064"), Operand::Immediate(0x64));
+        assert_eq!(Operand::parse("#-1"), Operand::Immediate(0xFFFFFFFF));
+        assert_eq!(Operand::parse("D8"), Operand::Other("D8".to_string()));
+        assert_eq!(Operand::parse("SR"), Operand::Other("SR".to_string()));
+    }
+
+    #[test]
+    fn parse_memory() {
+        // d16(An), with and without the effective address printed by fs-uae
+        let d16 = Operand::parse("(A6,$0022) == $00c00298");
+        assert_eq!(d16, memory("(A6,$0022)", Some(0x00C00298)));
+        assert_eq!(d16.displacement(), Some((6, 0x22)));
+        let mut step = CpuStep::with_instruction(0x1000, 0x1004, "TST.W (A5,-$0010)", 0x8000);
+        step.address[5] = 0x2000;
+        let negative = Operand::parse("(A5,-$0010)");
+        assert_eq!(negative.address(&step, Size::Word), Some(0x1FF0));
+        // d8(An,Xn)
+        let indexed = Operand::parse("(A0,D1.W*2,$04) == $00001238");
+        assert_eq!(indexed, memory("(A0,D1.W*2,$04)", Some(0x1238)));
+        assert_eq!(indexed.displacement(), None);
+        // absolute
+        assert_eq!(Operand::parse("$00dff180"), memory("$00dff180", Some(0x00DFF180)));
+        assert_eq!(Operand::parse("$0004.W"), memory("$0004.W", Some(4)));
+        // pre-decrement and post-increment
+        assert_eq!(Operand::parse("-(A7)").address(&step, Size::Byte), Some(0x7FFE));
+        assert_eq!(Operand::parse("(A5)+").address(&step, Size::Long), Some(0x2000));
+    }
+
+    #[test]
+    fn parse_list() {
+        assert_eq!(Operand::parse_list("(A0,D1.W*2,$04) == $00001238,D0"),
+                   [memory("(A0,D1.W*2,$04)", Some(0x1238)), Operand::DataReg(0)]);
+        assert_eq!(Operand::parse_list("#$0007,D0"), [Operand::Immediate(7), Operand::DataReg(0)]);
+        assert!(Operand::parse_list("").is_empty());
+    }
+}
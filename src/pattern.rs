@@ -0,0 +1,107 @@
+/*
+    Copyright (C) 2020 Arkadiusz Guzinski <kermit@ag.de1.cc>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::ops::Range;
+use crate::hunk::HunkFile;
+
+/// patterns with fewer unmasked bytes are not considered specific enough to be unique
+const MIN_UNMASKED: usize = 8;
+/// patterns that are not unique are extended up to this factor of the requested instructions
+pub const MAX_EXTENSION: usize = 8;
+
+/// a contiguous run of executed instructions, as pattern for Ghidra's instruction search
+pub struct PatternChunk {
+    /// (pc, bytes of the instruction, None if masked)
+    pub instructions: Vec<(u32, Vec<Option<u8>>)>,
+}
+
+impl PatternChunk {
+    /// address after the last instruction
+    pub fn end(&self) -> u32 {
+        self.instructions.last().map(|(pc, bytes)| pc + bytes.len() as u32).unwrap_or_default()
+    }
+
+    /// bytes of the instructions in range, with the gaps between them masked
+    pub fn bytes(&self, range: Range<usize>) -> Vec<Option<u8>> {
+        let mut bytes = Vec::new();
+        let mut expected = self.instructions[range.start].0;
+        for (pc, instruction) in &self.instructions[range] {
+            bytes.extend(std::iter::repeat_n(None, (pc - expected) as usize));
+            bytes.extend_from_slice(instruction);
+            expected = pc + instruction.len() as u32;
+        }
+        bytes
+    }
+
+    /// print the instructions in range in the format of Ghidra's instruction search (hex mode),
+    /// one per line, gaps on lines of their own
+    pub fn print(&self, range: Range<usize>) {
+        let first = self.instructions[range.start].0;
+        let (last, bytes) = &self.instructions[range.end - 1];
+        println!("# {:08X}-{:08X}: {} instructions", first, last + bytes.len() as u32 - 1, range.len());
+        let mut expected = first;
+        for (pc, instruction) in &self.instructions[range] {
+            if *pc > expected {
+                println!("{}", vec!["[........]"; (pc - expected) as usize].join(" "));
+            }
+            expected = pc + instruction.len() as u32;
+            let words: Vec<String> = instruction.chunks(2).map(|word| match word {
+                [Some(high), Some(low)] => format!("{:02x}{:02x}", high, low),
+                _ => "[........] [........]".to_string()
+            }).collect();
+            println!("{}", words.join(" "));
+        }
+    }
+}
+
+/// number of matches of each chunk in exe
+pub fn count_matches(chunks: &[PatternChunk], exe: &HunkFile) -> Vec<usize> {
+    chunks.iter().map(|c| exe.find_pattern(&c.bytes(0..c.instructions.len())).len()).collect()
+}
+
+/// the longest run of instructions in one of the chunks that is found exactly once in exe:
+/// (chunk, range of instructions, hunk, offset in the hunk). Whole chunks are preferred, chunks
+/// that are not found at all (e.g. due to missing masks) are shrunk.
+pub fn find_unique(chunks: &[PatternChunk], exe: &HunkFile) -> Option<(usize, Range<usize>, usize, u32)> {
+    let mut best: Option<(usize, Range<usize>, usize, u32)> = None;
+    for (c, chunk) in chunks.iter().enumerate() {
+        let len = chunk.instructions.len();
+        // shrinking a pattern found more than once doesn't make it unique
+        if exe.find_pattern(&chunk.bytes(0..len)).len() > 1 {
+            continue;
+        }
+        for size in (1..=len).rev() {
+            if best.as_ref().is_some_and(|(_, range, _, _)| range.len() >= size) {
+                break;
+            }
+            let unique = (0..=len - size).find_map(|start| {
+                let bytes = chunk.bytes(start..start + size);
+                if bytes.iter().flatten().count() < MIN_UNMASKED {
+                    return None;
+                }
+                match exe.find_pattern(&bytes).as_slice() {
+                    [(hunk, offset)] => Some((c, start..start + size, *hunk, *offset)),
+                    _ => None
+                }
+            });
+            if unique.is_some() {
+                best = unique;
+                break;
+            }
+        }
+    }
+    best
+}
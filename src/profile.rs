@@ -0,0 +1,91 @@
+/*
+    Copyright (C) 2020 Arkadiusz Guzinski <kermit@ag.de1.cc>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use crate::utils::FormatHelper;
+
+/// instructions executed per call stack. Stacks are lists of function entry pcs, outermost first.
+pub struct Profile {
+    pub stacks: HashMap<Vec<u32>, usize>,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile { stacks: HashMap::new() }
+    }
+
+    /// add count instructions executed with the given call stack
+    pub fn add(&mut self, stack: &[u32], count: usize) {
+        if count == 0 {
+            return;
+        }
+        if let Some(c) = self.stacks.get_mut(stack) {
+            *c += count;
+        } else {
+            self.stacks.insert(stack.to_vec(), count);
+        }
+    }
+
+    /// name of a stack frame. Folded stacks use ';' as separator, so it must not appear in names.
+    fn frame_name(entry: u32, fmt: &FormatHelper) -> String {
+        match fmt.name_for(entry) {
+            Some(name) => name.replace(';', "_"),
+            None => format!("{:08X}", fmt.with_offset(entry))
+        }
+    }
+
+    /// print in folded stack format (as used by flamegraph.pl, inferno, speedscope, ..)
+    pub fn print_folded(&self, fmt: &FormatHelper) {
+        let mut lines: Vec<String> = self.stacks.iter()
+            .map(|(stack, count)| format!("{} {}", stack.iter()
+                .map(|e| Profile::frame_name(*e, fmt))
+                .collect::<Vec<String>>().join(";"), count))
+            .collect();
+        lines.sort();
+        for line in lines {
+            println!("{}", line);
+        }
+    }
+
+    /// print inclusive and exclusive instruction counts per function, most expensive first
+    pub fn print_table(&self, fmt: &FormatHelper) {
+        // entry -> (inclusive, exclusive)
+        let mut counts: BTreeMap<u32, (usize, usize)> = BTreeMap::new();
+        let mut total = 0;
+        for (stack, count) in &self.stacks {
+            total += count;
+            // recursive functions count only once for inclusive
+            let unique: BTreeSet<&u32> = stack.iter().collect();
+            for entry in unique {
+                counts.entry(*entry).or_default().0 += count;
+            }
+            if let Some(leaf) = stack.last() {
+                counts.entry(*leaf).or_default().1 += count;
+            }
+        }
+        let mut sorted: Vec<(&u32, &(usize, usize))> = counts.iter().collect();
+        sorted.sort_by(|a, b| (b.1).0.cmp(&(a.1).0).then((b.1).1.cmp(&(a.1).1)));
+        println!("Inclusive\t%\tExclusive\t%\tFunction");
+        for (entry, (inclusive, exclusive)) in sorted {
+            println!("{}\t{:.2}\t{}\t{:.2}\t{}", inclusive, percent(*inclusive, total),
+                     exclusive, percent(*exclusive, total), fmt.pc(*entry));
+        }
+    }
+}
+
+fn percent(count: usize, total: usize) -> f64 {
+    if total == 0 { 0.0 } else { count as f64 * 100.0 / total as f64 }
+}
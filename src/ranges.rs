@@ -0,0 +1,132 @@
+/*
+    Copyright (C) 2020 Arkadiusz Guzinski <kermit@ag.de1.cc>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// ranges that apply unless the ranges file says otherwise: Kickstart ROM (256K ROMs are
+/// mirrored at 00F80000) and extended ROM (e.g. CD32, CDTV)
+const DEFAULT_RANGES: [(u32, u32, Region); 2] = [
+    (0x00F80000, 0x00FFFFFF, Region::Rom),
+    (0x00E00000, 0x00E7FFFF, Region::Rom),
+];
+
+/// what kind of code is found in a range of addresses
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Region {
+    /// Kickstart ROM
+    Rom,
+    /// operating system code in RAM, e.g. libraries and devices loaded from disk
+    Os,
+    /// the program being analysed. Addresses outside of all ranges belong to it, too.
+    Program,
+}
+
+impl Region {
+    /// parse the kind of a range in the ranges file
+    pub fn from_name(name: &str) -> Option<Region> {
+        match name.to_lowercase().as_str() {
+            "rom" => Some(Region::Rom),
+            "os" => Some(Region::Os),
+            "program" => Some(Region::Program),
+            _ => None
+        }
+    }
+
+    /// is this code of the system (ROM or OS), rather than of the program?
+    pub fn is_system(&self) -> bool {
+        *self != Region::Program
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Region::Rom => write!(f, "ROM"),
+            Region::Os => write!(f, "OS"),
+            Region::Program => write!(f, "program"),
+        }
+    }
+}
+
+/// how analyses treat steps in ROM and OS ranges
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RegionMode {
+    /// analyse all steps
+    Include,
+    /// ignore steps in ROM and OS code, but mention each excursion where possible
+    Collapse,
+    /// ignore steps in ROM and OS code
+    Exclude,
+}
+
+impl RegionMode {
+    /// parse value of the command line option
+    pub fn from_arg(arg: &str) -> RegionMode {
+        match arg {
+            "collapse" => RegionMode::Collapse,
+            "exclude" => RegionMode::Exclude,
+            _ => RegionMode::Include
+        }
+    }
+}
+
+/// address ranges classified as ROM, OS or program
+pub struct AddressRanges {
+    /// (first address, last address, kind). Earlier ranges take precedence.
+    pub ranges: Vec<(u32, u32, Region)>,
+}
+
+impl AddressRanges {
+    /// the default Kickstart ROM ranges
+    pub fn new() -> AddressRanges {
+        AddressRanges { ranges: DEFAULT_RANGES.to_vec() }
+    }
+
+    /// read ranges from file. Each line contains kind (rom, os or program), first and last address
+    /// (hex). Empty lines and lines starting with '#' are ignored. The default ranges are added
+    /// after those of the file.
+    pub fn from_file(file: File) -> AddressRanges {
+        let mut ranges = Vec::new();
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let range = match parts.as_slice() {
+                [kind, first, last] => Region::from_name(kind).and_then(|kind| Some((
+                    u32::from_str_radix(first, 16).ok()?, u32::from_str_radix(last, 16).ok()?, kind))),
+                _ => None
+            };
+            match range {
+                Some(range) => ranges.push(range),
+                None => eprintln!("ignoring invalid line in ranges file: {}", line)
+            }
+        }
+        ranges.extend_from_slice(&DEFAULT_RANGES);
+        AddressRanges { ranges }
+    }
+
+    /// kind of code at address
+    pub fn region_of(&self, address: u32) -> Region {
+        self.ranges.iter()
+            .find(|(first, last, _)| (*first..=*last).contains(&address))
+            .map(|(_, _, kind)| *kind)
+            .unwrap_or(Region::Program)
+    }
+}
@@ -0,0 +1,334 @@
+/*
+    Copyright (C) 2020 Arkadiusz Guzinski <kermit@ag.de1.cc>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use crate::cpustep::CpuStep;
+use flate2::read::ZlibDecoder;
+
+/// chunk flag: content is zlib compressed, preceded by its uncompressed size
+const CHUNK_COMPRESSED: u32 = 1;
+/// end of the A3000/A4000 motherboard RAM (A3K1 ends here, A3K2 starts here)
+const MOTHERBOARD_RAM_END: u32 = 0x08000000;
+/// bases of Zorro II and Zorro III fast RAM on real hardware, used if the savestate has no EXPA
+/// chunk. UAE may map Zorro III elsewhere, by default to 0x10000000.
+const Z2_FAST_BASE: u32 = 0x00200000;
+const Z3_FAST_BASE: u32 = 0x40000000;
+
+/// a ROM as described in a savestate
+pub struct SavedRom {
+    pub start: u32,
+    pub size: u32,
+    /// version in the upper, revision in the lower word
+    pub version: u32,
+    pub crc32: u32,
+    pub name: String,
+    pub path: String,
+    /// content, if it was saved with the state
+    pub data: Option<Vec<u8>>,
+}
+
+/// a UAE savestate (.uss), as written by WinUAE and FS-UAE. It consists of chunks with a four
+/// letter name, of which the RAM, CPU, ROM and custom chip chunks are decoded.
+pub struct SaveState {
+    /// emulator name and version, description of the state
+    pub emulator: String,
+    pub description: String,
+    /// name and size of all chunks in the file
+    pub chunks: Vec<(String, u32)>,
+    /// (chunk name, address, content) of RAM chunks
+    pub ram: Vec<(String, u32, Vec<u8>)>,
+    /// CPU model (e.g. 68020) and registers as step, with the prefetched words as pc_note
+    pub cpu: Option<(u32, CpuStep)>,
+    pub roms: Vec<SavedRom>,
+    /// chipset mask and the words of the custom chip registers 000-1FE
+    pub custom: Option<(u32, Vec<u16>)>,
+    /// bases of Zorro II and Zorro III fast RAM from the EXPA chunk, 0 if not configured
+    pub expansion: Option<(u32, u32)>,
+}
+
+impl SaveState {
+    /// load savestate from path
+    pub fn from_file(path: &Path) -> io::Result<SaveState> {
+        SaveState::parse(&fs::read(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// parse the content of a savestate
+    pub fn parse(data: &[u8]) -> Result<SaveState, String> {
+        if !data.starts_with(b"ASF ") {
+            return Err("not a UAE savestate (no ASF header)".to_string());
+        }
+        let mut state = SaveState { emulator: String::new(), description: String::new(), chunks: Vec::new(),
+            ram: Vec::new(), cpu: None, roms: Vec::new(), custom: None, expansion: None };
+        let mut pos = 0;
+        while pos + 12 <= data.len() {
+            let name = String::from_utf8_lossy(&data[pos..pos + 4]).to_string();
+            let mut header = Reader { data: &data[pos + 4..pos + 12], pos: 0 };
+            let len = header.long()? as usize;
+            let flags = header.long()?;
+            if len < 12 || pos + len > data.len() {
+                return Err(format!("invalid length of chunk {} at {:X}", name, pos));
+            }
+            let raw = &data[pos + 12..pos + len];
+            let content = if flags & CHUNK_COMPRESSED != 0 && raw.len() >= 4 {
+                // the zlib stream follows the uncompressed length
+                let mut content = Vec::new();
+                ZlibDecoder::new(&raw[4..]).read_to_end(&mut content)
+                    .map_err(|e| format!("chunk {}: {}", name, e))?;
+                content
+            } else {
+                raw.to_vec()
+            };
+            state.chunks.push((name.clone(), content.len() as u32));
+            state.chunk(&name, content)?;
+            if name == "END " {
+                break;
+            }
+            // chunks are padded to longs
+            pos += (len + 3) & !3;
+        }
+        // the EXPA chunk follows the RAM chunks
+        let expansion = state.expansion;
+        for (name, address, content) in state.ram.iter_mut() {
+            *address = SaveState::ram_address(name, content.len() as u32, expansion).unwrap_or(*address);
+        }
+        Ok(state)
+    }
+
+    /// decode the content of a chunk
+    fn chunk(&mut self, name: &str, content: Vec<u8>) -> Result<(), String> {
+        let mut reader = Reader { data: &content, pos: 0 };
+        match name {
+            "ASF " => {
+                reader.long()?;
+                let emulator = reader.string()?;
+                let version = reader.string()?;
+                self.emulator = format!("{} {}", emulator, version);
+                self.description = reader.string().unwrap_or_default();
+            }
+            "CPU " => self.cpu = Some(SaveState::cpu(&mut reader)?),
+            "ROM " => {
+                let mut rom = SavedRom { start: reader.long()?, size: reader.long()?, version: 0, crc32: 0,
+                    name: String::new(), path: String::new(), data: None };
+                reader.long()?;
+                rom.version = reader.long()?;
+                rom.crc32 = reader.long()?;
+                rom.name = reader.string()?;
+                rom.path = reader.string()?;
+                let rest = &content[reader.pos..];
+                if rest.len() >= rom.size as usize && rom.size > 0 {
+                    rom.data = Some(rest[..rom.size as usize].to_vec());
+                }
+                self.roms.push(rom);
+            }
+            "EXPA" => self.expansion = Some((reader.long()?, reader.long()?)),
+            "CHIP" => {
+                let mask = reader.long()?;
+                let mut registers = Vec::new();
+                while registers.len() < 0x100 {
+                    match reader.word() {
+                        Ok(word) => registers.push(word),
+                        Err(_) => break
+                    }
+                }
+                self.custom = Some((mask, registers));
+            }
+            _ => {
+                if let Some(address) = SaveState::ram_address(name, content.len() as u32, None) {
+                    self.ram.push((name.to_string(), address, content));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// address of the RAM saved in chunk name: chip, slow (bogo), Zorro II fast, Zorro III fast
+    /// and motherboard RAM. Fast RAM is at the base configured in expansion (Zorro II, Zorro III),
+    /// or at its usual base without it.
+    fn ram_address(name: &str, size: u32, expansion: Option<(u32, u32)>) -> Option<u32> {
+        let (z2, z3) = expansion.unwrap_or((0, 0));
+        match name {
+            "CRAM" => Some(0x00000000),
+            "BRAM" => Some(0x00C00000),
+            "FRAM" => Some(if z2 != 0 { z2 } else { Z2_FAST_BASE }),
+            "ZRAM" => Some(if z3 != 0 { z3 } else { Z3_FAST_BASE }),
+            "A3K1" => Some(MOTHERBOARD_RAM_END - size),
+            "A3K2" => Some(MOTHERBOARD_RAM_END),
+            _ => None
+        }
+    }
+
+    /// decode the CPU chunk: model, flags, D0-D7, A0-A6, PC, prefetch, USP, ISP, SR, mode and the
+    /// control registers of the model
+    fn cpu(reader: &mut Reader) -> Result<(u32, CpuStep), String> {
+        let model = reader.long()?;
+        reader.long()?;
+        let mut registers = [0u32; 15];
+        for register in registers.iter_mut() {
+            *register = reader.long()?;
+        }
+        let pc = reader.long()?;
+        let irc = reader.word()?;
+        let ir = reader.word()?;
+        let usp = reader.long()?;
+        let isp = reader.long()?;
+        let sr = reader.word()?;
+        let mode = reader.long()?;
+        let (mut sfc, mut dfc, mut vbr, mut caar, mut cacr, mut msp) = (0, 0, 0, 0, 0, 0);
+        if model >= 68010 {
+            dfc = reader.long()?;
+            sfc = reader.long()?;
+            vbr = reader.long()?;
+        }
+        if model >= 68020 {
+            caar = reader.long()?;
+            cacr = reader.long()?;
+            msp = reader.long()?;
+        }
+        let (s, m) = (sr & 0x2000 != 0, sr & 0x1000 != 0);
+        let mut data = [0u32; 8];
+        data.copy_from_slice(&registers[..8]);
+        let mut address = [0u32; 8];
+        address[..7].copy_from_slice(&registers[8..]);
+        address[7] = match (s, m) {
+            (false, _) => usp,
+            (true, false) => isp,
+            (true, true) => msp,
+        };
+        let pc_note = format!("{:04x} {:04x}", ir, irc).into_bytes();
+        let note = b"(savestate)";
+        let step = CpuStep {
+            data,
+            address,
+            usp, isp, sfc, dfc, cacr, vbr, caar, msp,
+            t: (sr >> 14) as u8,
+            s,
+            m,
+            x: sr & 0x10 != 0,
+            n: sr & 0x8 != 0,
+            z: sr & 0x4 != 0,
+            v: sr & 0x2 != 0,
+            c: sr & 0x1 != 0,
+            imask: ((sr >> 8) & 7) as u8,
+            stp: mode & 1 != 0,
+            pc,
+            pc_note: array_init::array_init(|i| *pc_note.get(i).unwrap_or(&0x20)),
+            note: array_init::array_init(|i| *note.get(i).unwrap_or(&0x20)),
+            pc_next: pc,
+        };
+        Ok((model, step))
+    }
+}
+
+/// reads big endian values and zero terminated strings from the content of a chunk
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let data: &'a [u8] = self.data;
+        let bytes = data.get(self.pos..self.pos + count).ok_or("unexpected end of chunk")?;
+        self.pos += count;
+        Ok(bytes)
+    }
+
+    fn long(&mut self) -> Result<u32, String> {
+        self.bytes(4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn word(&mut self) -> Result<u16, String> {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let rest = self.data.get(self.pos..).unwrap_or_default();
+        let len = rest.iter().position(|b| *b == 0).ok_or("unterminated string")?;
+        self.pos += len + 1;
+        Ok(String::from_utf8_lossy(&rest[..len]).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// chunk with header and padding
+    fn chunk(name: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut chunk = name.to_vec();
+        chunk.extend_from_slice(&(content.len() as u32 + 12).to_be_bytes());
+        chunk.extend_from_slice(&0u32.to_be_bytes());
+        chunk.extend_from_slice(content);
+        chunk.resize((chunk.len() + 3) & !3, 0);
+        chunk
+    }
+
+    #[test]
+    fn parse_minimal_state() {
+        let mut data = chunk(b"ASF ", b"\0\0\0\0FS-UAE\x003.1.66\0test\0");
+        data.extend(chunk(b"CRAM", &[1, 2, 3, 4, 5, 6]));
+        data.extend(chunk(b"END ", &[]));
+        let state = SaveState::parse(&data).unwrap();
+        assert_eq!(state.emulator, "FS-UAE 3.1.66");
+        assert_eq!(state.description, "test");
+        assert_eq!(state.ram, vec![("CRAM".to_string(), 0, vec![1, 2, 3, 4, 5, 6])]);
+        let names: Vec<&str> = state.chunks.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["ASF ", "CRAM", "END "]);
+    }
+
+    #[test]
+    fn fast_ram_bases() {
+        let mut data = chunk(b"ASF ", b"\0\0\0\0FS-UAE\x003.1.66\0\0");
+        data.extend(chunk(b"FRAM", &[0; 8]));
+        data.extend(chunk(b"ZRAM", &[0; 8]));
+        let without = SaveState::parse(&data).unwrap();
+        assert_eq!(without.ram.iter().map(|(_, a, _)| *a).collect::<Vec<u32>>(), [0x00200000, 0x40000000]);
+        data.extend(chunk(b"EXPA", &[0x00, 0x20, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00]));
+        let with = SaveState::parse(&data).unwrap();
+        assert_eq!(with.ram.iter().map(|(_, a, _)| *a).collect::<Vec<u32>>(), [0x00200000, 0x10000000]);
+    }
+
+    #[test]
+    fn compressed_chunk() {
+        // zlib stream with a dynamic Huffman block
+        let content = b"ccaabbddabcdaabcbaedadaaaaaaaebacbcaabca";
+        let zlib = [0x78, 0xDA, 0x1D, 0xCA, 0xB1, 0x01, 0x00, 0x30, 0x0C, 0x83, 0xB0, 0x5B, 0x31, 0xCE,
+            0xFF, 0x2F, 0x34, 0x29, 0x93, 0x06, 0x14, 0x92, 0x96, 0xD8, 0x95, 0x61, 0xCA, 0xEA, 0x37, 0xC1,
+            0xDC, 0x20, 0x0F, 0x3A, 0xDF, 0x0F, 0x56];
+        let compressed = |zlib: &[u8]| {
+            let mut chunk = chunk(b"CRAM", &[&(content.len() as u32).to_be_bytes()[..], zlib].concat());
+            chunk[8..12].copy_from_slice(&CHUNK_COMPRESSED.to_be_bytes());
+            chunk
+        };
+        let mut data = chunk(b"ASF ", b"\0\0\0\0FS-UAE\x003.1.66\0\0");
+        data.extend(compressed(&zlib));
+        let state = SaveState::parse(&data).unwrap();
+        assert_eq!(state.ram, vec![("CRAM".to_string(), 0, content.to_vec())]);
+        assert_eq!(state.chunks[1], ("CRAM".to_string(), content.len() as u32));
+        // truncated stream
+        let mut data = chunk(b"ASF ", b"\0\0\0\0FS-UAE\x003.1.66\0\0");
+        data.extend(compressed(&zlib[..20]));
+        assert!(SaveState::parse(&data).is_err());
+    }
+
+    #[test]
+    fn not_a_savestate() {
+        assert!(SaveState::parse(b"XYZ ").is_err());
+    }
+}
@@ -0,0 +1,119 @@
+/*
+    Copyright (C) 2020 Arkadiusz Guzinski <kermit@ag.de1.cc>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+
+/// a hunk of the program, as loaded in the dump
+#[derive(Clone, Debug)]
+pub struct HunkSegment {
+    /// number of the hunk in the executable
+    pub hunk: usize,
+    /// first and last address in the dump
+    pub first: u32,
+    pub last: u32,
+    /// address of the hunk in Ghidra
+    pub ghidra: u32,
+}
+
+impl HunkSegment {
+    /// parse a line of the segments file
+    fn parse(line: &str) -> Option<HunkSegment> {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            [hunk, first, last, ghidra] => Some(HunkSegment {
+                hunk: hunk.parse().ok()?,
+                first: u32::from_str_radix(first, 16).ok()?,
+                last: u32::from_str_radix(last, 16).ok()?,
+                ghidra: u32::from_str_radix(ghidra, 16).ok()?,
+            }),
+            _ => None
+        }
+    }
+}
+
+/// translates addresses in the dump to addresses in Ghidra: addresses inside a hunk segment are
+/// translated relative to the hunk, all others by subtracting the single offset
+#[derive(Clone, Debug)]
+pub struct SegmentMap {
+    pub segments: Vec<HunkSegment>,
+    pub offset: u32,
+}
+
+impl SegmentMap {
+    /// map without segments, using offset for all addresses
+    pub fn new(offset: u32) -> SegmentMap {
+        SegmentMap { segments: Vec::new(), offset }
+    }
+
+    /// read segments from file. Each line contains hunk number (decimal), first and last address
+    /// in the dump and address in Ghidra (hex). Empty lines and lines starting with '#' are ignored.
+    pub fn from_file(file: File, offset: u32) -> SegmentMap {
+        let mut segments = Vec::new();
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match HunkSegment::parse(line) {
+                Some(segment) => segments.push(segment),
+                None => eprintln!("ignoring invalid line in segments file: {}", line)
+            }
+        }
+        SegmentMap { segments, offset }
+    }
+
+    /// write segments in the format read by from_file
+    pub fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "# hunk first last ghidra")?;
+        for s in &self.segments {
+            writeln!(out, "{} {:08X} {:08X} {:08X}", s.hunk, s.first, s.last, s.ghidra)?;
+        }
+        Ok(())
+    }
+
+    /// does the map leave all addresses unchanged?
+    pub fn is_identity(&self) -> bool {
+        self.segments.is_empty() && self.offset == 0
+    }
+
+    /// segment containing address in the dump
+    pub fn segment_of(&self, address: u32) -> Option<&HunkSegment> {
+        self.segments.iter().find(|s| (s.first..=s.last).contains(&address))
+    }
+
+    /// address in Ghidra for an address in the dump, None if it is below the offset
+    pub fn translate(&self, address: u32) -> Option<u32> {
+        match self.segment_of(address) {
+            Some(s) => Some(s.ghidra + (address - s.first)),
+            None => address.checked_sub(self.offset)
+        }
+    }
+
+    /// address in Ghidra for an address in the dump. Addresses below the offset wrap around, for
+    /// output that needs an address for every value.
+    pub fn translate_or_wrap(&self, address: u32) -> u32 {
+        self.translate(address).unwrap_or_else(|| address.wrapping_sub(self.offset))
+    }
+
+    /// address in the dump for an address in Ghidra
+    pub fn to_dump(&self, address: u32) -> u32 {
+        self.segments.iter()
+            .find(|s| (s.ghidra..=s.ghidra + (s.last - s.first)).contains(&address))
+            .map(|s| s.first + (address - s.ghidra))
+            .unwrap_or_else(|| address.wrapping_add(self.offset))
+    }
+}
@@ -0,0 +1,383 @@
+/*
+    Copyright (C) 2020 Arkadiusz Guzinski <kermit@ag.de1.cc>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use crate::cpustep::CpuStep;
+
+/// maximum number of user stacks kept after task switches
+const MAX_STASHED: usize = 64;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FrameKind {
+    /// caller that was active before the dump started, recovered from the stack
+    Outer,
+    /// function active before the dump started, that was not yet returned from
+    Root,
+    /// subroutine call (JSR, BSR)
+    Call,
+    /// exception, trap or interrupt
+    Exception,
+}
+
+/// an entry of the shadow call stack
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub kind: FrameKind,
+    /// index of the step that made the call or was interrupted
+    pub index: usize,
+    /// first pc of the called function or exception handler
+    pub entry: u32,
+    /// address of the return address (or exception stack frame) on the real stack. The frame is
+    /// left, when the stack pointer is moved above it.
+    pub sp: u32,
+    /// expected return address, 0 if unknown
+    pub return_address: u32,
+}
+
+/// the stacks of the 68k: user (USP), interrupt (ISP) and master (MSP)
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StackSelect { User = 0, Interrupt = 1, Master = 2 }
+
+impl StackSelect {
+    /// stack that is active at step
+    pub fn of(step: &CpuStep) -> StackSelect {
+        if !step.s {
+            StackSelect::User
+        } else if step.m {
+            StackSelect::Master
+        } else {
+            StackSelect::Interrupt
+        }
+    }
+
+    /// current value of this stack's pointer at step. The active one is A7, the others are read
+    /// from USP, ISP and MSP, which fs-uae only updates when switching stacks.
+    pub fn sp(&self, step: &CpuStep) -> u32 {
+        if StackSelect::of(step) == *self {
+            return step.address[7];
+        }
+        match self {
+            StackSelect::User => step.usp,
+            StackSelect::Interrupt => step.isp,
+            StackSelect::Master => step.msp
+        }
+    }
+}
+
+/// changes to the shadow stack, caused by one step
+#[derive(Default)]
+pub struct StackChange {
+    /// frame that was entered (call or exception)
+    pub entered: Option<Frame>,
+    /// frames that were left, innermost first
+    pub left: Vec<Frame>,
+    /// set if the left frames include a root, i.e. returned into a function that was active
+    /// before the dump started
+    pub underflow: bool,
+}
+
+/// call stack, tracking calls and exceptions by their effects on the real stack pointers.
+///
+/// A frame is pushed for JSR/BSR (when the stack pointer is lowered by 4) and for exceptions.
+/// Frames are removed whenever the stack pointer of their stack is moved above them, no matter
+/// by which instruction (RTS, RTD, RTR, RTE, MOVE.L (SP)+, stack switches, ...). Jumps that don't
+/// touch the stack (tail calls via JMP/BRA) don't change the stack.
+pub struct ShadowStack {
+    stacks: [Vec<Frame>; 3],
+    /// highest stack pointer seen per stack. Moving above it means leaving a root frame.
+    base: [u32; 3],
+    /// user stacks, that were switched away from (e.g. by a task switch), by stack pointer
+    stashed: Vec<(u32, Vec<Frame>)>,
+    /// user stack pointer when supervisor mode was entered
+    user_sp: Option<u32>,
+    /// number of root frames that were left
+    pub underflow: usize,
+}
+
+impl ShadowStack {
+    /// create a shadow stack for a dump starting with step first
+    pub fn new(first: &CpuStep) -> ShadowStack {
+        let mut shadow = ShadowStack {
+            stacks: [Vec::new(), Vec::new(), Vec::new()],
+            base: [StackSelect::User.sp(first), StackSelect::Interrupt.sp(first),
+                StackSelect::Master.sp(first)],
+            stashed: Vec::new(),
+            user_sp: if first.s { Some(first.usp) } else { None },
+            underflow: 0,
+        };
+        shadow.stacks[StackSelect::User as usize].push(ShadowStack::root(first.pc, 0));
+        shadow
+    }
+
+    /// add callers that were active before the dump started, outermost first. They are placed
+    /// below the root frame and take its place, when it returns to their return address.
+    pub fn push_outer(&mut self, frames: Vec<Frame>) {
+        let user = &mut self.stacks[StackSelect::User as usize];
+        let pos = user.iter().position(|f| f.kind == FrameKind::Root).unwrap_or(0);
+        user.splice(pos..pos, frames);
+    }
+
+    fn root(entry: u32, index: usize) -> Frame {
+        Frame { kind: FrameKind::Root, index, entry, sp: u32::MAX, return_address: 0 }
+    }
+
+    /// does the step to next enter an exception handler?
+    pub fn is_exception(step: &CpuStep, next: &CpuStep) -> bool {
+        if !step.s && next.s {
+            return true;
+        }
+        if next.pc == step.pc_next || !next.s {
+            return false;
+        }
+        // already in supervisor mode: interrupts raise the mask, traps are known by name or opcode
+        next.imask > step.imask
+            || (matches!(step.mnemonic(), "TRAP" | "TRAPV" | "ILLEGAL" | "CHK" | "CHK2" | "DIVU" | "DIVS")
+                || matches!(step.opcode().map(|o| o >> 12), Some(0xA) | Some(0xF)))
+                && next.address[7] < step.address[7]
+    }
+
+    /// frames that are active at step, outermost first. Supervisor frames are only included in
+    /// supervisor mode.
+    pub fn chain(&self, step: &CpuStep) -> Vec<&Frame> {
+        let mut frames: Vec<&Frame> = self.stacks[StackSelect::User as usize].iter().collect();
+        if step.s {
+            let select = StackSelect::of(step);
+            frames.extend(self.stacks[StackSelect::Interrupt as usize].iter());
+            if select == StackSelect::Master {
+                frames.extend(self.stacks[StackSelect::Master as usize].iter());
+            }
+        }
+        frames
+    }
+
+    /// call depth at step, relative to the function active at the start of the dump
+    pub fn depth(&self, step: &CpuStep) -> i16 {
+        (self.chain(step).iter().filter(|f| !matches!(f.kind, FrameKind::Root | FrameKind::Outer)).count() as i16)
+            - self.underflow as i16
+    }
+
+    /// is any exception frame active at step?
+    pub fn in_exception(&self, step: &CpuStep) -> bool {
+        self.chain(step).iter().any(|f| f.kind == FrameKind::Exception)
+    }
+
+    /// update with the transition from step (index idx) to next
+    pub fn update(&mut self, idx: usize, step: &CpuStep, next: &CpuStep) -> StackChange {
+        let mut change = StackChange::default();
+        let before = StackSelect::of(step);
+        let after = StackSelect::of(next);
+
+        // returning to user mode with a different stack pointer, e.g. by a task switch
+        if before != StackSelect::User && after == StackSelect::User {
+            if let Some(saved) = self.user_sp.take() {
+                let sp = next.address[7];
+                if sp != saved {
+                    let user = std::mem::take(&mut self.stacks[StackSelect::User as usize]);
+                    self.stashed.push((saved, user));
+                    if self.stashed.len() > MAX_STASHED {
+                        self.stashed.remove(0);
+                    }
+                    self.stacks[StackSelect::User as usize] =
+                        match self.stashed.iter().position(|(s, _)| *s == sp) {
+                            Some(pos) => self.stashed.remove(pos).1,
+                            None => vec![ShadowStack::root(next.pc, idx + 1)]
+                        };
+                    self.base[StackSelect::User as usize] = sp;
+                }
+            }
+        } else if before == StackSelect::User && after != StackSelect::User {
+            self.user_sp = Some(step.address[7]);
+        }
+
+        // leave frames the stack pointers have moved above
+        for select in [StackSelect::User, StackSelect::Interrupt, StackSelect::Master].iter() {
+            let s = *select as usize;
+            let sp = select.sp(next);
+            while let Some(top) = self.stacks[s].last() {
+                if matches!(top.kind, FrameKind::Root | FrameKind::Outer) || top.sp >= sp {
+                    break;
+                }
+                change.left.push(self.stacks[s].pop().unwrap());
+            }
+            if sp > self.base[s] {
+                // returned from a function that was called before the dump started
+                if *select == before && *select == after
+                    && matches!(step.mnemonic(), "RTS" | "RTD" | "RTR") {
+                    if let Some(pos) = self.stacks[s].iter().rposition(|f| f.kind == FrameKind::Root) {
+                        change.left.push(self.stacks[s].remove(pos));
+                        match self.stacks[s].get_mut(pos.wrapping_sub(1)) {
+                            // returned into a recovered caller
+                            Some(outer) if outer.kind == FrameKind::Outer && outer.return_address == next.pc => {
+                                outer.kind = FrameKind::Root;
+                                outer.index = idx + 1;
+                            }
+                            _ => self.stacks[s].insert(pos, ShadowStack::root(next.pc, idx + 1))
+                        }
+                        self.underflow += 1;
+                        change.underflow = true;
+                    }
+                }
+                self.base[s] = sp;
+            }
+        }
+
+        if ShadowStack::is_exception(step, next) {
+            let frame = Frame {
+                kind: FrameKind::Exception,
+                index: idx,
+                entry: next.pc,
+                sp: next.address[7],
+                return_address: 0,
+            };
+            self.stacks[after as usize].push(frame.clone());
+            change.entered = Some(frame);
+        } else if before == after && matches!(step.mnemonic(), "JSR" | "BSR")
+            && next.address[7] == step.address[7].wrapping_sub(4) {
+            let frame = Frame {
+                kind: FrameKind::Call,
+                index: idx,
+                entry: next.pc,
+                sp: next.address[7],
+                return_address: step.pc_next,
+            };
+            self.stacks[after as usize].push(frame.clone());
+            change.entered = Some(frame);
+        }
+        change
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(pc: u32, pc_next: u32, instruction: &str, sp: u32) -> CpuStep {
+        CpuStep::with_instruction(pc, pc_next, instruction, sp)
+    }
+
+    /// feed all steps to a new shadow stack, returning it and the change of every step
+    fn walk(steps: &[CpuStep]) -> (ShadowStack, Vec<StackChange>) {
+        let mut shadow = ShadowStack::new(&steps[0]);
+        let changes = steps.windows(2).enumerate().map(|(i, w)| shadow.update(i, &w[0], &w[1])).collect();
+        (shadow, changes)
+    }
+
+    fn entries(frames: &[Frame]) -> Vec<u32> {
+        frames.iter().map(|f| f.entry).collect()
+    }
+
+    #[test]
+    fn call_and_return() {
+        let steps = [
+            step(0x1000, 0x1006, "JSR $00002000", 0x8000),
+            step(0x2000, 0x2002, "NOP", 0x7FFC),
+            step(0x2002, 0x2004, "RTS", 0x7FFC),
+            step(0x1006, 0x1008, "NOP", 0x8000),
+        ];
+        let (shadow, changes) = walk(&steps);
+        let call = changes[0].entered.as_ref().unwrap();
+        assert_eq!((call.kind, call.index, call.entry, call.sp, call.return_address),
+                   (FrameKind::Call, 0, 0x2000, 0x7FFC, 0x1006));
+        assert!(changes[1].entered.is_none() && changes[1].left.is_empty());
+        assert_eq!(entries(&changes[2].left), [0x2000]);
+        assert!(!changes[2].underflow);
+        assert_eq!(shadow.depth(&steps[3]), 0);
+    }
+
+    #[test]
+    fn tail_call() {
+        // the JMP continues in the frame of the caller, the RTS of the target leaves it
+        let steps = [
+            step(0x1000, 0x1006, "JSR $00002000", 0x8000),
+            step(0x2000, 0x2006, "JMP $00003000", 0x7FFC),
+            step(0x3000, 0x3002, "RTS", 0x7FFC),
+            step(0x1006, 0x1008, "NOP", 0x8000),
+        ];
+        let (shadow, changes) = walk(&steps);
+        assert!(changes[1].entered.is_none() && changes[1].left.is_empty());
+        assert_eq!(entries(&changes[2].left), [0x2000]);
+        assert_eq!(shadow.depth(&steps[3]), 0);
+    }
+
+    #[test]
+    fn pop_return_address() {
+        // the return address is taken from the stack and jumped to
+        let mut steps = [
+            step(0x1000, 0x1006, "JSR $00002000", 0x8000),
+            step(0x2000, 0x2002, "MOVE.L (A7)+,A0", 0x7FFC),
+            step(0x2002, 0x2004, "JMP (A0)", 0x8000),
+            step(0x1006, 0x1008, "NOP", 0x8000),
+        ];
+        steps[2].address[0] = 0x1006;
+        let (shadow, changes) = walk(&steps);
+        assert_eq!(entries(&changes[1].left), [0x2000]);
+        assert!(!changes[1].underflow);
+        assert!(changes[2].left.is_empty());
+        assert_eq!(shadow.depth(&steps[3]), 0);
+    }
+
+    #[test]
+    fn exception_switches_stacks() {
+        // the interrupt stack is above the user stack, so it must not leave user frames
+        let mut steps = [
+            step(0x1000, 0x1006, "JSR $00002000", 0x8000),
+            step(0x2000, 0x2002, "NOP", 0x7FFC),
+            step(0x500, 0x502, "RTE", 0x8FFA),
+            step(0x2000, 0x2002, "NOP", 0x7FFC),
+            step(0x2002, 0x2004, "RTS", 0x7FFC),
+            step(0x1006, 0x1008, "NOP", 0x8000),
+        ];
+        for s in steps.iter_mut() {
+            s.usp = 0x8000;
+            s.isp = 0x9000;
+        }
+        steps[2].s = true;
+        steps[2].usp = 0x7FFC;
+        steps[3].usp = 0x7FFC;
+        let (shadow, changes) = walk(&steps);
+        let exception = changes[1].entered.as_ref().unwrap();
+        assert_eq!((exception.kind, exception.index, exception.entry, exception.sp),
+                   (FrameKind::Exception, 1, 0x500, 0x8FFA));
+        assert!(changes[1].left.is_empty());
+        let (inside, _) = walk(&steps[..3]);
+        assert!(inside.in_exception(&steps[2]));
+        assert_eq!(inside.depth(&steps[2]), 2);
+        assert_eq!(entries(&changes[2].left), [0x500]);
+        assert_eq!(entries(&changes[4].left), [0x2000]);
+        assert_eq!(shadow.depth(&steps[5]), 0);
+    }
+
+    #[test]
+    fn unwind_frames() {
+        // three nested calls are left at once by restoring the stack pointer, then the function
+        // active at the start returns
+        let steps = [
+            step(0x1000, 0x1006, "JSR $00002000", 0x8000),
+            step(0x2000, 0x2006, "JSR $00003000", 0x7FFC),
+            step(0x3000, 0x3006, "JSR $00004000", 0x7FF8),
+            step(0x4000, 0x4002, "MOVEA.L A5,A7", 0x7FF4),
+            step(0x1100, 0x1102, "RTS", 0x8000),
+            step(0x500, 0x502, "NOP", 0x8004),
+        ];
+        let (inside, _) = walk(&steps[..4]);
+        assert_eq!(inside.depth(&steps[3]), 3);
+        let (shadow, changes) = walk(&steps);
+        assert_eq!(entries(&changes[3].left), [0x4000, 0x3000, 0x2000]);
+        assert!(!changes[3].underflow);
+        assert_eq!(changes[4].left.iter().map(|f| f.kind).collect::<Vec<_>>(), [FrameKind::Root]);
+        assert!(changes[4].underflow);
+        assert_eq!(shadow.depth(&steps[5]), -1);
+    }
+}
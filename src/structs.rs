@@ -0,0 +1,231 @@
+/*
+    Copyright (C) 2020 Arkadiusz Guzinski <kermit@ag.de1.cc>
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use crate::memdump::MemDump;
+
+/// type of a field in a structure description
+#[derive(Clone, PartialEq, Debug)]
+pub enum FieldType {
+    Byte,
+    SByte,
+    Word,
+    SWord,
+    Long,
+    SLong,
+    /// fixed size character array (char[n])
+    Chars(u32),
+    /// pointer to a zero terminated string (char*)
+    CString,
+    /// pointer to a described structure, or to anything (void*)
+    Pointer(Option<String>),
+    /// structure embedded in another one
+    Struct(String),
+}
+
+impl FieldType {
+    /// parse a type name: byte, sbyte, word, sword, long, slong, char[n], char*, void*, Name* or
+    /// the name of a structure described before
+    fn parse(name: &str, structs: &BTreeMap<String, StructDef>) -> Option<FieldType> {
+        if let Some(target) = name.strip_suffix('*') {
+            return match target {
+                "char" => Some(FieldType::CString),
+                "void" => Some(FieldType::Pointer(None)),
+                _ => Some(FieldType::Pointer(Some(target.to_string())))
+            };
+        }
+        if let Some(len) = name.strip_prefix("char[").and_then(|l| l.strip_suffix(']')) {
+            return len.parse().ok().map(FieldType::Chars);
+        }
+        match name {
+            "byte" | "ubyte" => Some(FieldType::Byte),
+            "sbyte" => Some(FieldType::SByte),
+            "word" | "uword" => Some(FieldType::Word),
+            "sword" => Some(FieldType::SWord),
+            "long" | "ulong" => Some(FieldType::Long),
+            "slong" => Some(FieldType::SLong),
+            _ if structs.contains_key(name) => Some(FieldType::Struct(name.to_string())),
+            _ => None
+        }
+    }
+
+    /// size in bytes
+    fn size(&self, structs: &BTreeMap<String, StructDef>) -> u32 {
+        match self {
+            FieldType::Byte | FieldType::SByte => 1,
+            FieldType::Word | FieldType::SWord => 2,
+            FieldType::Long | FieldType::SLong | FieldType::CString | FieldType::Pointer(_) => 4,
+            FieldType::Chars(len) => *len,
+            FieldType::Struct(name) => structs.get(name).map(|s| s.size).unwrap_or_default(),
+        }
+    }
+
+    /// the value at address in mem, formatted for display. "??" if it is outside the dump.
+    pub fn read(&self, mem: &MemDump, address: u32) -> String {
+        let value = match self {
+            FieldType::Byte => mem.byte_at(address).map(|v| format!("{:02X} ({})", v, v)),
+            FieldType::SByte => mem.sbyte_at(address).map(|v| v.to_string()),
+            FieldType::Word => mem.word_at(address).map(|v| format!("{:04X} ({})", v, v)),
+            FieldType::SWord => mem.sword_at(address).map(|v| v.to_string()),
+            FieldType::Long => mem.long_at(address).map(|v| format!("{:08X} ({})", v, v)),
+            FieldType::SLong => mem.slong_at(address).map(|v| v.to_string()),
+            FieldType::Chars(len) => mem.chars_at(address, *len as usize).map(|s| format!("\"{}\"", s)),
+            FieldType::CString => mem.long_at(address).map(|p| match mem.string_at(p, 80) {
+                Some(s) => format!("{:08X} \"{}\"", p, s),
+                None => format!("{:08X}", p)
+            }),
+            FieldType::Struct(_) => Some(format!("{:08X}", address)),
+            FieldType::Pointer(_) => mem.long_at(address).map(|p| match p {
+                0 => "NULL".to_string(),
+                _ if mem.contains(p) => format!("{:08X}", p),
+                _ => format!("{:08X} (outside of dump)", p)
+            }),
+        };
+        value.unwrap_or_else(|| "??".to_string())
+    }
+}
+
+impl fmt::Display for FieldType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldType::Byte => write!(f, "byte"),
+            FieldType::SByte => write!(f, "sbyte"),
+            FieldType::Word => write!(f, "word"),
+            FieldType::SWord => write!(f, "sword"),
+            FieldType::Long => write!(f, "long"),
+            FieldType::SLong => write!(f, "slong"),
+            FieldType::Chars(len) => write!(f, "char[{}]", len),
+            FieldType::CString => write!(f, "char*"),
+            FieldType::Pointer(None) => write!(f, "void*"),
+            FieldType::Pointer(Some(name)) => write!(f, "{}*", name),
+            FieldType::Struct(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// a field of a structure
+#[derive(Clone, Debug)]
+pub struct Field {
+    pub offset: u32,
+    pub name: String,
+    pub kind: FieldType,
+    /// number of elements, 1 unless the field is an array
+    pub count: u32,
+}
+
+/// a structure as described in the structs file
+#[derive(Clone, Debug)]
+pub struct StructDef {
+    pub name: String,
+    pub fields: Vec<Field>,
+    pub size: u32,
+}
+
+/// structure descriptions, by name
+pub struct Structs {
+    pub structs: BTreeMap<String, StructDef>,
+}
+
+impl Structs {
+    /// read structure descriptions from file. A description starts with a line "struct Name" and
+    /// optionally its size (hex). Each following line describes a field: optionally its offset
+    /// (hex, prefixed by '+'), type, name and optionally the number of elements, e.g.
+    /// "+1A word x", "char[16] name", "Character* next" or "long scores 4". Fields without offset
+    /// follow the previous one. Empty lines and lines starting with '#' are ignored.
+    pub fn from_file(file: File) -> Structs {
+        let mut structs: BTreeMap<String, StructDef> = BTreeMap::new();
+        let mut current: Option<StructDef> = None;
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts[0] == "struct" {
+                if let Some(done) = current.take() {
+                    structs.insert(done.name.clone(), done);
+                }
+                match parts.as_slice() {
+                    [_, name] => current = Some(StructDef { name: name.to_string(), fields: Vec::new(), size: 0 }),
+                    [_, name, size] if u32::from_str_radix(size, 16).is_ok() => current = Some(StructDef {
+                        name: name.to_string(), fields: Vec::new(), size: u32::from_str_radix(size, 16).unwrap()
+                    }),
+                    _ => eprintln!("ignoring invalid line in structs file: {}", line)
+                }
+                continue;
+            }
+            let def = match current.as_mut() {
+                Some(def) => def,
+                None => {
+                    eprintln!("ignoring field outside of struct in structs file: {}", line);
+                    continue;
+                }
+            };
+            match Structs::parse_field(&parts, def, &structs) {
+                Some(field) => {
+                    def.size = def.size.max(field.offset + field.count * field.kind.size(&structs));
+                    def.fields.push(field);
+                }
+                None => eprintln!("ignoring invalid line in structs file: {}", line)
+            }
+        }
+        if let Some(done) = current.take() {
+            structs.insert(done.name.clone(), done);
+        }
+        Structs { structs }
+    }
+
+    /// parse a field line, split into parts
+    fn parse_field(parts: &[&str], def: &StructDef, structs: &BTreeMap<String, StructDef>) -> Option<Field> {
+        let (offset, parts) = match parts[0].strip_prefix('+') {
+            Some(offset) => (u32::from_str_radix(offset, 16).ok()?, &parts[1..]),
+            None => (def.fields.last().map(|f| f.offset + f.count * f.kind.size(structs)).unwrap_or_default(), parts)
+        };
+        let (kind, name, count) = match parts {
+            [kind, name] => (kind, name, 1),
+            [kind, name, count] => (kind, name, count.parse().ok()?),
+            _ => return None
+        };
+        Some(Field { offset, name: name.to_string(), kind: FieldType::parse(kind, structs)?, count })
+    }
+
+    /// fields of structure name with their offset, embedded structures and arrays expanded.
+    /// Names of the parts are joined by '.', elements numbered in brackets.
+    pub fn flatten(&self, name: &str) -> Vec<(u32, String, FieldType)> {
+        let mut fields = Vec::new();
+        if let Some(def) = self.structs.get(name) {
+            for field in &def.fields {
+                let size = field.kind.size(&self.structs);
+                for i in 0..field.count {
+                    let offset = field.offset + i * size;
+                    let name = match field.count {
+                        1 => field.name.clone(),
+                        _ => format!("{}[{}]", field.name, i)
+                    };
+                    match &field.kind {
+                        FieldType::Struct(inner) => fields.extend(self.flatten(inner).into_iter()
+                            .map(|(o, n, k)| (offset + o, format!("{}.{}", name, n), k))),
+                        kind => fields.push((offset, name, kind.clone()))
+                    }
+                }
+            }
+        }
+        fields
+    }
+}
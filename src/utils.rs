@@ -15,7 +15,7 @@
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::{File};
 use std::io::{BufReader, BufRead, Read};
 use std::cmp::Ordering;
@@ -24,6 +24,11 @@ use crate::utils::Visibility::{Hidden, Brief};
 use std::path::{PathBuf};
 use roxmltree::{Document, ParsingOptions};
 use std::iter::Peekable;
+use crate::lvo::Libraries;
+use crate::context::InterruptMode;
+use crate::ranges::{AddressRanges, RegionMode};
+use crate::kickstart::Kickstart;
+use crate::segments::SegmentMap;
 
 #[derive(Eq, PartialEq)]
 pub enum Visibility { Hidden, Brief, Verbose }
@@ -38,12 +43,24 @@ pub struct FormatHelper {
     pub colors: bool,
     /// how much space to use to indent per step of the call hierarchy
     pub indent: i16,
-    /// pc offset for disassembler
-    pub offset_mod: u32,
+    /// translation of pcs to addresses for disassembler
+    pub segments: SegmentMap,
     pub print_both_offsets: bool,
     pub func_names: Visibility,
+    /// collapse loops in summaries?
+    pub collapse_loops: bool,
     ///
     pub show_interrupt: Visibility,
+    /// how to treat steps in interrupt handlers
+    pub interrupts: InterruptMode,
+    /// how to treat steps in ROM and OS code
+    pub regions: RegionMode,
+    /// address ranges of ROM, OS and program code
+    pub ranges: AddressRanges,
+    /// known libraries, used to annotate library calls
+    pub libs: Libraries,
+    /// Kickstart ROM image, used for ROM memory and names of ROM routines
+    pub kickstart: Option<Kickstart>,
     info: GhidraInfo,
 }
 
@@ -56,7 +73,9 @@ struct GhidraFun {
 
 struct GhidraInfo {
     functions: BTreeSet<GhidraFun>,
-    offset: u32,
+    /// address -> (name, size) of labels (SYMBOL_TABLE), size taken from DEFINED_DATA or 1
+    labels: BTreeMap<u32, (String, u32)>,
+    segments: SegmentMap,
 }
 
 impl FormatHelper {
@@ -116,11 +135,17 @@ impl FormatHelper {
             compact,
             colors: true,
             indent: 2,
-            offset_mod: 0,
+            segments: SegmentMap::new(0),
             print_both_offsets: true,
             func_names: Visibility::Verbose,
+            collapse_loops: true,
             show_interrupt: Visibility::Brief,
-            info: GhidraInfo { functions: BTreeSet::new(), offset: 0 },
+            interrupts: InterruptMode::Include,
+            regions: RegionMode::Include,
+            ranges: AddressRanges::new(),
+            kickstart: None,
+            libs: Libraries::new(),
+            info: GhidraInfo { functions: BTreeSet::new(), labels: BTreeMap::new(), segments: SegmentMap::new(0) },
         }
     }
 
@@ -131,11 +156,17 @@ impl FormatHelper {
             compact,
             colors: false,
             indent: 2,
-            offset_mod: 0,
+            segments: SegmentMap::new(0),
             print_both_offsets: true,
             func_names: Visibility::Brief,
+            collapse_loops: true,
             show_interrupt: Visibility::Brief,
-            info: GhidraInfo { functions: BTreeSet::new(), offset: 0 },
+            interrupts: InterruptMode::Include,
+            regions: RegionMode::Include,
+            ranges: AddressRanges::new(),
+            kickstart: None,
+            libs: Libraries::new(),
+            info: GhidraInfo { functions: BTreeSet::new(), labels: BTreeMap::new(), segments: SegmentMap::new(0) },
         }
     }
 
@@ -152,20 +183,20 @@ impl FormatHelper {
 
         match args.value_of("offset-mode") {
             Some("dump") => {
-                self.offset_mod = 0;
+                self.segments = SegmentMap::new(0);
                 self.print_both_offsets = false;
             }
             Some("translated") => {
-                self.offset_mod = FormatHelper::get_offset(&args);
+                self.segments = FormatHelper::get_segments(args);
                 self.print_both_offsets = false
             }
             Some("both") => {
-                self.offset_mod = FormatHelper::get_offset(&args);
+                self.segments = FormatHelper::get_segments(args);
                 self.print_both_offsets = true
             }
             _ => {
                 if self.print_both_offsets {
-                    self.offset_mod = FormatHelper::get_offset(&args);
+                    self.segments = FormatHelper::get_segments(args);
                 }
             }
         }
@@ -178,13 +209,42 @@ impl FormatHelper {
         }
 
         if self.func_names != Hidden {
-            self.info.load(args, self.offset_mod);
+            self.info.load(args, self.segments.clone());
+        }
+
+        let rom_path = match args.value_of("kickstart") {
+            Some(path) => Some(PathBuf::from(path)),
+            None => FormatHelper::path_in_dir_or_parent(args, "kickstart.rom")
+        };
+        if let Some(path) = rom_path {
+            match Kickstart::load(&path, &self.libs) {
+                Ok(rom) => {
+                    eprintln!("using {} with {} symbols", rom.description, rom.symbols.len());
+                    self.kickstart = Some(rom);
+                }
+                Err(e) => eprintln!("could not load Kickstart ROM {}: {}", path.display(), e)
+            }
+        }
+
+        if args.is_present("raw") {
+            self.collapse_loops = false;
         }
 
         if args.is_present("traps") {
             self.show_interrupt = Brief;
         }
 
+        if let Some(mode) = args.value_of("interrupt-mode") {
+            self.interrupts = InterruptMode::from_arg(mode);
+        }
+
+        if let Some(mode) = args.value_of("rom-mode") {
+            self.regions = RegionMode::from_arg(mode);
+        }
+        if let Some(file) = FormatHelper::file_in_dir_or_parent(args, "ranges") {
+            self.ranges = AddressRanges::from_file(file);
+        }
+
         // todo load ghidra info
 
         return self;
@@ -192,7 +252,11 @@ impl FormatHelper {
 
     /// load offset from path/offset or 0 if file is missing
     pub fn get_offset(args: &ArgMatches) -> u32 {
-        let file_offset = FormatHelper::file_in_dir_or_parent(&args, "offset");
+        FormatHelper::read_offset(FormatHelper::file_in_dir_or_parent(args, "offset"))
+    }
+
+    /// offset from file, 0 if there is none
+    fn read_offset(file_offset: Option<File>) -> u32 {
         match file_offset {
             Some(file) => {
                 let mut buf_reader = BufReader::new(file);
@@ -204,12 +268,29 @@ impl FormatHelper {
         }
     }
 
+    /// load segments from path/segments, translating other addresses by the offset
+    pub fn get_segments(args: &ArgMatches) -> SegmentMap {
+        let offset = FormatHelper::get_offset(args);
+        match FormatHelper::file_in_dir_or_parent(args, "segments") {
+            Some(file) => SegmentMap::from_file(file, offset),
+            None => SegmentMap::new(offset)
+        }
+    }
+
+    /// load segments and offset of another dump than the one in the dir argument, from dir or its
+    /// parent. Its program may have been loaded elsewhere.
+    pub fn segments_of(dir: &str) -> SegmentMap {
+        let file = |f_name| FormatHelper::path_in_or_parent(PathBuf::from(dir), f_name).and_then(|p| File::open(p).ok());
+        let offset = FormatHelper::read_offset(file("offset"));
+        match file("segments") {
+            Some(file) => SegmentMap::from_file(file, offset),
+            None => SegmentMap::new(offset)
+        }
+    }
+
+    /// address in Ghidra for address in the dump
     pub fn with_offset(&self, address: u32) -> u32 {
-        return if address >= self.offset_mod {
-            address - self.offset_mod
-        } else {
-            address + 0xf0000000
-        };
+        self.segments.translate(address).unwrap_or_else(|| address.wrapping_add(0xf0000000))
     }
 
     pub fn pc(&self, pc: u32) -> String {
@@ -217,14 +298,14 @@ impl FormatHelper {
             (Hidden, false) => format!("{:08X}", self.with_offset(pc)),
             (Hidden, true) => format!("{:08X} ({:08X})", self.with_offset(pc), pc),
             (_, false) => {
-                if let Some(s) = self.info.name_for(pc) {
+                if let Some(s) = self.function_name(pc) {
                     format!("{} ({:08X})", s, self.with_offset(pc))
                 } else {
                     format!("{:08X}", self.with_offset(pc))
                 }
             }
             (_, true) => {
-                if let Some(s) = self.info.name_for(pc) {
+                if let Some(s) = self.function_name(pc) {
                     format!("{} ({:08X}, {:08X})", s, self.with_offset(pc), pc)
                 } else {
                     format!("{:08X} ({:08X})", self.with_offset(pc), pc)
@@ -233,6 +314,36 @@ impl FormatHelper {
         }
     }
 
+    /// name of the (Ghidra) function or ROM routine containing pc, if known and function names are
+    /// enabled
+    pub fn name_for(&self, pc: u32) -> Option<String> {
+        if self.func_names == Hidden {
+            return None;
+        }
+        self.function_name(pc)
+    }
+
+    /// name of the Ghidra function or, in the Kickstart ROM, symbol containing pc
+    fn function_name(&self, pc: u32) -> Option<String> {
+        self.info.name_for(pc).or_else(|| self.kickstart.as_ref()?.name_for(pc))
+    }
+
+    /// Ghidra label (data or function) containing address, if known, and the hunk segment it is in
+    pub fn label_for(&self, address: u32) -> Option<String> {
+        let label = self.info.label_for(address);
+        let segment = self.segments.segment_of(address)
+            .map(|s| format!("hunk {} +${:X}", s.hunk, address - s.first));
+        match (label, segment) {
+            (Some(label), Some(segment)) => Some(format!("{} ({})", label, segment)),
+            (label, segment) => label.or(segment)
+        }
+    }
+
+    /// entry of the (Ghidra) function or ROM routine containing pc, if known
+    pub fn function_start(&self, pc: u32) -> Option<u32> {
+        self.info.function_start(pc).or_else(|| self.kickstart.as_ref()?.function_start(pc))
+    }
+
     pub fn padding(&self, depth: i16) -> String {
         let pad_max: usize = 60;
         let pad: usize = if depth >= 0 { (depth * self.indent) as usize } else { 0 };
@@ -244,9 +355,23 @@ impl FormatHelper {
         };
     }
 
+    /// separator between groups of changes in summaries
+    pub fn delimiter(&self, depth: i16) -> String {
+        if self.compact {
+            "  ".to_string()
+        } else {
+            format!("\n{}", self.padding(depth))
+        }
+    }
+
     pub fn file_in_dir_or_parent(args: &ArgMatches, f_name: &str) -> Option<File> {
+        File::open(FormatHelper::path_in_dir_or_parent(args, f_name)?).ok()
+    }
+
+    /// path of file f_name in dir or its parent, if it exists
+    pub fn path_in_dir_or_parent(args: &ArgMatches, f_name: &str) -> Option<PathBuf> {
         let mut p: Peekable<Values>;
-        let mut path = PathBuf::from(
+        let path = PathBuf::from(
             if let Some(d) = args.value_of("dir") {
                 d
             } else if let Some(d) = args.value_of("set_dir") {
@@ -261,14 +386,19 @@ impl FormatHelper {
             } else {
                 return None;
             });
+        FormatHelper::path_in_or_parent(path, f_name)
+    }
+
+    /// path of file f_name in dir path or its parent, if it exists
+    fn path_in_or_parent(mut path: PathBuf, f_name: &str) -> Option<PathBuf> {
         path.push(f_name);
         if !path.exists() {
             path.pop();
             path.pop();
             path.push(f_name);
         }
-        if let Ok(file) = File::open(path) {
-            Some(file)
+        if path.exists() {
+            Some(path)
         } else {
             None
         }
@@ -276,8 +406,8 @@ impl FormatHelper {
 }
 
 impl GhidraInfo {
-    pub fn load(&mut self, args: &ArgMatches, offset: u32) {
-        self.offset = offset;
+    pub fn load(&mut self, args: &ArgMatches, segments: SegmentMap) {
+        self.segments = segments;
         if let Some(mut file) = FormatHelper::file_in_dir_or_parent(&args, "functions.xml") {
             let mut content = String::new();
             if file.read_to_string(&mut content).is_err() {
@@ -308,18 +438,60 @@ impl GhidraInfo {
                                 self.functions.insert(gf);
                             }
                         }
+                        self.load_labels(&xml);
                     },
                 Err(e) => println!("Error loading functions.xml: {}", e)
             }
         }
     }
 
+    /// read labels from SYMBOL_TABLE and their sizes from DATA
+    fn load_labels(&mut self, xml: &Document) {
+        let hex = |n: &roxmltree::Node, attribute: &str| {
+            let value = n.attribute(attribute)?;
+            u32::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+        };
+        for symbol in xml.descendants().filter(|n| n.has_tag_name("SYMBOL")) {
+            if let (Some(address), Some(name)) = (hex(&symbol, "ADDRESS"), symbol.attribute("NAME")) {
+                self.labels.insert(address, (name.to_string(), 1));
+            }
+        }
+        for data in xml.descendants().filter(|n| n.has_tag_name("DEFINED_DATA")) {
+            if let (Some(address), Some(size)) = (hex(&data, "ADDRESS"), hex(&data, "SIZE")) {
+                if let Some(label) = self.labels.get_mut(&address) {
+                    label.1 = size.max(1);
+                }
+            }
+        }
+    }
+
     pub fn name_for(&self, address: u32) -> Option<String> {
-        let pc = address.wrapping_sub(self.offset);
+        self.function_at(address).map(|f| f.name.to_owned())
+    }
+
+    /// label containing address, with the offset into it (e.g. "party+$1C"), or the function
+    pub fn label_for(&self, address: u32) -> Option<String> {
+        let translated = self.segments.translate(address)?;
+        match self.labels.range(..=translated).next_back() {
+            Some((start, (name, size))) if translated - start < *size => match translated - start {
+                0 => Some(name.clone()),
+                offset => Some(format!("{}+${:X}", name, offset))
+            },
+            _ => self.name_for(address)
+        }
+    }
+
+    /// entry of the function containing address, in dump addresses
+    pub fn function_start(&self, address: u32) -> Option<u32> {
+        self.function_at(address).map(|f| self.segments.to_dump(f.start))
+    }
+
+    fn function_at(&self, address: u32) -> Option<&GhidraFun> {
+        let pc = self.segments.translate(address)?;
         let tmp = GhidraFun { start: pc, end: pc, name: String::new() };
         if let Some(closest) = self.functions.range(..=tmp).next_back() {
             if (closest.start..=closest.end).contains(&pc) {
-                return Some(closest.name.to_owned());
+                return Some(closest);
             }
         }
         None